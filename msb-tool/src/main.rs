@@ -0,0 +1,339 @@
+use std::{
+    fs,
+    io::{Cursor, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
+use clap::{Parser, ValueEnum};
+use msb::{
+    analysis::reachable, command::TypedFlowEntry, dot::to_dot, parse_msbf, parse_msbt, Msbf, Msbt,
+};
+use u8file::U8File;
+
+#[derive(Debug, Parser)]
+enum Cli {
+    /// write a .msbt or .msbf file as YAML or JSON
+    Dump {
+        input: PathBuf,
+        /// defaults to stdout
+        #[clap(short, long)]
+        output: Option<PathBuf>,
+        /// defaults to the extension of the output file, or YAML
+        #[clap(short, long)]
+        format: Option<Format>,
+    },
+    /// build a .msbt or .msbf file from a dump, the type is taken from the output extension
+    Build {
+        input: PathBuf,
+        output: PathBuf,
+        /// defaults to the extension of the input file, or YAML
+        #[clap(short, long)]
+        format: Option<Format>,
+    },
+    /// print the flows of a .msbf file
+    Flow {
+        msbf: PathBuf,
+        /// shows the texts of text entries
+        #[clap(long)]
+        msbt: Option<PathBuf>,
+        /// only print the entries reachable from this entrypoint
+        #[clap(short, long, conflicts_with = "dot")]
+        entrypoint: Option<String>,
+        /// print the graph in the Graphviz DOT format
+        #[clap(long)]
+        dot: bool,
+    },
+    /// search all messages for a string, directories are searched recursively and
+    /// .arc and .arc.LZ files are searched for .msbt files
+    Search {
+        query: String,
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
+        #[clap(short, long)]
+        ignore_case: bool,
+    },
+    /// look up a label of a message or an entrypoint of a flow, in the same files as search
+    Label {
+        label: String,
+        #[clap(required = true)]
+        paths: Vec<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Format {
+    Yaml,
+    Json,
+}
+
+impl Format {
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => Self::Json,
+            _ => Self::Yaml,
+        }
+    }
+}
+
+enum MsbFile {
+    Msbt(Msbt),
+    Msbf(Msbf),
+}
+
+fn parse_msb(data: &[u8]) -> anyhow::Result<MsbFile> {
+    match data.get(..8) {
+        Some(b"MsgStdBn") => Ok(MsbFile::Msbt(parse_msbt(&mut Cursor::new(data))?)),
+        Some(b"MsgFlwBn") => Ok(MsbFile::Msbf(parse_msbf(&mut Cursor::new(data))?)),
+        _ => bail!("not a .msbt or .msbf file"),
+    }
+}
+
+fn read_msb(path: &Path) -> anyhow::Result<MsbFile> {
+    let data = fs::read(path).with_context(|| format!("failed to read {path:?}"))?;
+    parse_msb(&data).with_context(|| format!("failed to parse {path:?}"))
+}
+
+/// reads all .msbt and .msbf files in the paths, returns the name and contents of every file
+fn collect_files(paths: &[PathBuf]) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect_files_rec(path, &mut files)?;
+    }
+    Ok(files)
+}
+
+fn collect_files_rec(path: &Path, files: &mut Vec<(String, Vec<u8>)>) -> anyhow::Result<()> {
+    if path.is_dir() {
+        let mut entries = fs::read_dir(path)
+            .with_context(|| format!("failed to read {path:?}"))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        entries.sort();
+        for entry in entries {
+            collect_files_rec(&entry, files)?;
+        }
+        return Ok(());
+    }
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("msbt" | "msbf") => {
+            let data = fs::read(path).with_context(|| format!("failed to read {path:?}"))?;
+            files.push((path.display().to_string(), data));
+        }
+        // stage archives are compressed as .arc.LZ
+        Some("arc" | "LZ") => {
+            let arc = U8File::open(path).with_context(|| format!("failed to read {path:?}"))?;
+            for entry_path in arc.get_all_paths() {
+                if entry_path.ends_with(".msbt") || entry_path.ends_with(".msbf") {
+                    let entry_data = arc.get_entry_data(&entry_path).unwrap().to_vec();
+                    files.push((format!("{}:{entry_path}", path.display()), entry_data));
+                }
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn dump(input: &Path, output: Option<&Path>, format: Option<Format>) -> anyhow::Result<()> {
+    let format = format.unwrap_or_else(|| output.map_or(Format::Yaml, Format::from_path));
+    let mut dumped = match (read_msb(input)?, format) {
+        (MsbFile::Msbt(msbt), Format::Yaml) => serde_yaml::to_string(&msbt)?,
+        (MsbFile::Msbt(msbt), Format::Json) => serde_json::to_string_pretty(&msbt)?,
+        (MsbFile::Msbf(msbf), Format::Yaml) => serde_yaml::to_string(&msbf)?,
+        (MsbFile::Msbf(msbf), Format::Json) => serde_json::to_string_pretty(&msbf)?,
+    };
+    if !dumped.ends_with('\n') {
+        dumped.push('\n');
+    }
+    match output {
+        Some(output) => {
+            fs::write(output, dumped).with_context(|| format!("failed to write {output:?}"))?
+        }
+        None => std::io::stdout().write_all(dumped.as_bytes())?,
+    }
+    Ok(())
+}
+
+fn build(input: &Path, output: &Path, format: Option<Format>) -> anyhow::Result<()> {
+    let format = format.unwrap_or_else(|| Format::from_path(input));
+    let text = fs::read_to_string(input).with_context(|| format!("failed to read {input:?}"))?;
+    let mut buf = Cursor::new(Vec::new());
+    match output.extension().and_then(|ext| ext.to_str()) {
+        Some("msbt") => {
+            let msbt: Msbt = match format {
+                Format::Yaml => serde_yaml::from_str(&text)?,
+                Format::Json => serde_json::from_str(&text)?,
+            };
+            msbt.write_msbt(&mut buf)?;
+        }
+        Some("msbf") => {
+            let msbf: Msbf = match format {
+                Format::Yaml => serde_yaml::from_str(&text)?,
+                Format::Json => serde_json::from_str(&text)?,
+            };
+            msbf.write_msbf(&mut buf)?;
+        }
+        _ => bail!("output {output:?} has to end with .msbt or .msbf"),
+    }
+    fs::write(output, buf.into_inner()).with_context(|| format!("failed to write {output:?}"))?;
+    Ok(())
+}
+
+fn print_flow(
+    msbf_path: &Path,
+    msbt_path: Option<&Path>,
+    entrypoint: Option<&str>,
+    dot: bool,
+) -> anyhow::Result<()> {
+    let MsbFile::Msbf(mut msbf) = read_msb(msbf_path)? else {
+        bail!("{msbf_path:?} is not a .msbf file");
+    };
+    let msbt = match msbt_path.map(read_msb).transpose()? {
+        Some(MsbFile::Msbt(msbt)) => Some(msbt),
+        Some(MsbFile::Msbf(_)) => bail!("{:?} is not a .msbt file", msbt_path.unwrap()),
+        None => None,
+    };
+    if dot {
+        print!("{}", to_dot(&msbf, msbt.as_ref()));
+        return Ok(());
+    }
+    if let Some(entrypoint) = entrypoint {
+        let Some(index) = msbf.entrypoints.get(entrypoint).copied() else {
+            bail!("entrypoint {entrypoint} doesn't exist");
+        };
+        msbf.entrypoints.retain(|_, i| *i == index);
+    }
+    let mut entrypoints: Vec<(&String, &u32)> = msbf.entrypoints.iter().collect();
+    entrypoints.sort_unstable();
+    for (name, index) in entrypoints {
+        println!("{name} -> {index}");
+    }
+    let next = |next: i16| {
+        if next == -1 {
+            "end".to_string()
+        } else {
+            next.to_string()
+        }
+    };
+    for (index, (flow, reachable)) in msbf.flows.iter().zip(reachable(&msbf)).enumerate() {
+        if entrypoint.is_some() && !reachable {
+            continue;
+        }
+        match TypedFlowEntry::from(flow.clone()) {
+            TypedFlowEntry::Start { next: n } => println!("{index}: start -> {}", next(n)),
+            TypedFlowEntry::Text {
+                file,
+                line,
+                next: n,
+            } => {
+                let text = msbt
+                    .as_ref()
+                    .and_then(|msbt| msbt.text.get(line as usize))
+                    .map(|text| format!(" {:?}", text.markup()))
+                    .unwrap_or_default();
+                println!("{index}: text {file}/{line}{text} -> {}", next(n));
+            }
+            TypedFlowEntry::Command { command, next: n } => {
+                println!("{index}: {command:?} -> {}", next(n))
+            }
+            TypedFlowEntry::Switch {
+                condition,
+                branches,
+            } => {
+                let branches: Vec<String> = branches.into_iter().map(next).collect();
+                println!("{index}: switch {condition:?} -> [{}]", branches.join(", "));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn search(query: &str, paths: &[PathBuf], ignore_case: bool) -> anyhow::Result<()> {
+    let query = if ignore_case {
+        query.to_lowercase()
+    } else {
+        query.to_string()
+    };
+    for (name, data) in collect_files(paths)? {
+        let MsbFile::Msbt(msbt) =
+            parse_msb(&data).with_context(|| format!("failed to parse {name}"))?
+        else {
+            continue;
+        };
+        let mut labels: Vec<(&u32, &String)> = msbt.lbl.iter().map(|(l, i)| (i, l)).collect();
+        labels.sort_unstable();
+        for (index, text) in msbt.text.iter().enumerate() {
+            let markup = text.markup();
+            let matches = if ignore_case {
+                markup.to_lowercase().contains(&query)
+            } else {
+                markup.contains(&query)
+            };
+            if matches {
+                let label = labels
+                    .iter()
+                    .find(|(i, _)| **i as usize == index)
+                    .map_or("", |(_, label)| label.as_str());
+                println!("{name} {index} {label}: {markup:?}");
+            }
+        }
+    }
+    Ok(())
+}
+
+fn find_label(label: &str, paths: &[PathBuf]) -> anyhow::Result<()> {
+    let mut found = false;
+    for (name, data) in collect_files(paths)? {
+        match parse_msb(&data).with_context(|| format!("failed to parse {name}"))? {
+            MsbFile::Msbt(msbt) => {
+                if let Some(&index) = msbt.lbl.get(label) {
+                    let markup = msbt
+                        .text
+                        .get(index as usize)
+                        .map(|text| text.markup())
+                        .unwrap_or_default();
+                    println!("{name} text {index}: {markup:?}");
+                    found = true;
+                }
+            }
+            MsbFile::Msbf(msbf) => {
+                if let Some(index) = msbf.entrypoints.get(label) {
+                    println!("{name} entrypoint {index}");
+                    found = true;
+                }
+            }
+        }
+    }
+    if !found {
+        bail!("label {label} not found");
+    }
+    Ok(())
+}
+
+fn main() -> anyhow::Result<()> {
+    match Cli::parse() {
+        Cli::Dump {
+            input,
+            output,
+            format,
+        } => dump(&input, output.as_deref(), format),
+        Cli::Build {
+            input,
+            output,
+            format,
+        } => build(&input, &output, format),
+        Cli::Flow {
+            msbf,
+            msbt,
+            entrypoint,
+            dot,
+        } => print_flow(&msbf, msbt.as_deref(), entrypoint.as_deref(), dot),
+        Cli::Search {
+            query,
+            paths,
+            ignore_case,
+        } => search(&query, &paths, ignore_case),
+        Cli::Label { label, paths } => find_label(&label, &paths),
+    }
+}
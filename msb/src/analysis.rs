@@ -0,0 +1,197 @@
+//! checks for broken events, meant to be run after editing flows
+
+use crate::{command::Condition, FlowEntry, Msbf, Msbt};
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum Issue {
+    #[error("entrypoint {name} points to {index}, which doesn't exist")]
+    EntrypointOutOfRange { name: String, index: u32 },
+    #[error("next of {index} is {next}, which doesn't exist")]
+    NextOutOfRange { index: usize, next: i16 },
+    #[error("branch {branch} of {index} is {target}, which doesn't exist")]
+    BranchOutOfRange {
+        index: usize,
+        branch: usize,
+        target: i16,
+    },
+    #[error("switch {index} has {found} branches, but its condition needs {expected}")]
+    BranchCountMismatch {
+        index: usize,
+        expected: usize,
+        found: usize,
+    },
+    #[error("text {index} shows line {line}, which doesn't exist")]
+    MissingText { index: usize, line: u16 },
+    #[error("{index} can't be reached from any entrypoint")]
+    Unreachable { index: usize },
+}
+
+/// checks all flows for problems, text lines are only checked if the Msbt is given
+pub fn analyze(msbf: &Msbf, msbt: Option<&Msbt>) -> Vec<Issue> {
+    let flow_count = msbf.flows.len();
+    let in_range = |target: i16| target == -1 || (target >= 0 && (target as usize) < flow_count);
+    let mut issues = Vec::new();
+
+    let mut entrypoints: Vec<(&String, &u32)> = msbf.entrypoints.iter().collect();
+    entrypoints.sort_unstable();
+    for (name, &index) in entrypoints {
+        if index as usize >= flow_count {
+            issues.push(Issue::EntrypointOutOfRange {
+                name: name.clone(),
+                index,
+            });
+        }
+    }
+
+    for (index, flow) in msbf.flows.iter().enumerate() {
+        match flow {
+            FlowEntry::Start { next } | FlowEntry::Flow { next, .. } => {
+                if !in_range(*next) {
+                    issues.push(Issue::NextOutOfRange { index, next: *next });
+                }
+            }
+            FlowEntry::Text { line, next, .. } => {
+                if !in_range(*next) {
+                    issues.push(Issue::NextOutOfRange { index, next: *next });
+                }
+                if msbt.is_some_and(|msbt| *line as usize >= msbt.text.len()) {
+                    issues.push(Issue::MissingText { index, line: *line });
+                }
+            }
+            FlowEntry::Switch {
+                subtype,
+                param1,
+                param2,
+                param3,
+                branches,
+            } => {
+                for (branch, &target) in branches.iter().enumerate() {
+                    if !in_range(target) {
+                        issues.push(Issue::BranchOutOfRange {
+                            index,
+                            branch,
+                            target,
+                        });
+                    }
+                }
+                let condition = Condition::from_raw(*subtype, *param1, *param2, *param3);
+                if let Some(expected) = condition.branch_count() {
+                    if expected != branches.len() {
+                        issues.push(Issue::BranchCountMismatch {
+                            index,
+                            expected,
+                            found: branches.len(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    issues.extend(
+        reachable(msbf)
+            .iter()
+            .enumerate()
+            .filter(|(_, reachable)| !**reachable)
+            .map(|(index, _)| Issue::Unreachable { index }),
+    );
+    issues
+}
+
+/// returns for every flow entry if it can be reached from an entrypoint
+pub fn reachable(msbf: &Msbf) -> Vec<bool> {
+    let flow_count = msbf.flows.len();
+    let mut reachable = vec![false; flow_count];
+    let mut to_visit: Vec<usize> = msbf
+        .entrypoints
+        .values()
+        .map(|&index| index as usize)
+        .filter(|&index| index < flow_count)
+        .collect();
+    while let Some(index) = to_visit.pop() {
+        if reachable[index] {
+            continue;
+        }
+        reachable[index] = true;
+        for target in successors(&msbf.flows[index]) {
+            if target >= 0 && (target as usize) < flow_count {
+                to_visit.push(target as usize);
+            }
+        }
+    }
+    reachable
+}
+
+fn successors(flow: &FlowEntry) -> Vec<i16> {
+    match flow {
+        FlowEntry::Start { next } | FlowEntry::Text { next, .. } | FlowEntry::Flow { next, .. } => {
+            vec![*next]
+        }
+        FlowEntry::Switch { branches, .. } => branches.clone(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{analyze, Issue};
+    use crate::{FlowEntry, Msbf, Msbt, TextSegment};
+
+    #[test]
+    pub fn test_analyze() {
+        let msbf = Msbf {
+            flows: vec![
+                FlowEntry::Start { next: 1 },
+                // check storyflag with 3 branches
+                FlowEntry::Switch {
+                    subtype: 6,
+                    param1: 0,
+                    param2: 5,
+                    param3: 3,
+                    branches: vec![2, -1, 7],
+                },
+                FlowEntry::Text {
+                    file: 0,
+                    line: 1,
+                    next: -1,
+                },
+                FlowEntry::Text {
+                    file: 0,
+                    line: 0,
+                    next: -5,
+                },
+            ],
+            entrypoints: HashMap::from([("100_00".to_string(), 0), ("100_01".to_string(), 9)]),
+            ..Default::default()
+        };
+        let msbt = Msbt {
+            text: vec![TextSegment::default()],
+            ..Default::default()
+        };
+        assert_eq!(
+            analyze(&msbf, Some(&msbt)),
+            vec![
+                Issue::EntrypointOutOfRange {
+                    name: "100_01".to_string(),
+                    index: 9
+                },
+                Issue::BranchOutOfRange {
+                    index: 1,
+                    branch: 2,
+                    target: 7
+                },
+                Issue::BranchCountMismatch {
+                    index: 1,
+                    expected: 2,
+                    found: 3
+                },
+                Issue::MissingText { index: 2, line: 1 },
+                Issue::NextOutOfRange { index: 3, next: -5 },
+                Issue::Unreachable { index: 3 },
+            ]
+        );
+        // without the text, lines can't be checked
+        assert!(!analyze(&msbf, None).contains(&Issue::MissingText { index: 2, line: 1 }));
+    }
+}
@@ -0,0 +1,313 @@
+//! typed versions of the known Skyward Sword event commands and switch conditions
+//!
+//! everything that isn't known (or has unexpected parameters) stays raw, so converting
+//! a `FlowEntry` to a `TypedFlowEntry` and back always gives the same entry
+
+use crate::FlowEntry;
+
+/// commands of `Flow` entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    SetStoryflag(u16),
+    UnsetStoryflag(u16),
+    SetSceneflag {
+        scene: u16,
+        flag: u16,
+    },
+    UnsetSceneflag {
+        scene: u16,
+        flag: u16,
+    },
+    AddRupees(i16),
+    GiveItem(u16),
+    TriggerExit {
+        roomid: u16,
+        exit: u16,
+    },
+    GiveGratitudeCrystals,
+    Raw {
+        subtype: u8,
+        param1: u16,
+        param2: u16,
+        param3: i16,
+    },
+}
+
+impl Command {
+    pub fn from_raw(subtype: u8, param1: u16, param2: u16, param3: i16) -> Self {
+        match (subtype, param1, param2, param3) {
+            (0, 0, flag, 0) => Self::SetStoryflag(flag),
+            (0, 0, flag, 1) => Self::UnsetStoryflag(flag),
+            (0, scene, flag, 2) => Self::SetSceneflag { scene, flag },
+            (0, scene, flag, 3) => Self::UnsetSceneflag { scene, flag },
+            (0, 0, rupees, 8) => Self::AddRupees(rupees as i16),
+            (0, 0, item, 9) => Self::GiveItem(item),
+            (0, roomid, exit, 19) => Self::TriggerExit { roomid, exit },
+            (0, 0, 0, 43) => Self::GiveGratitudeCrystals,
+            _ => Self::Raw {
+                subtype,
+                param1,
+                param2,
+                param3,
+            },
+        }
+    }
+
+    /// returns subtype, param1, param2 and param3
+    pub fn to_raw(&self) -> (u8, u16, u16, i16) {
+        match *self {
+            Self::SetStoryflag(flag) => (0, 0, flag, 0),
+            Self::UnsetStoryflag(flag) => (0, 0, flag, 1),
+            Self::SetSceneflag { scene, flag } => (0, scene, flag, 2),
+            Self::UnsetSceneflag { scene, flag } => (0, scene, flag, 3),
+            Self::AddRupees(rupees) => (0, 0, rupees as u16, 8),
+            Self::GiveItem(item) => (0, 0, item, 9),
+            Self::TriggerExit { roomid, exit } => (0, roomid, exit, 19),
+            Self::GiveGratitudeCrystals => (0, 0, 0, 43),
+            Self::Raw {
+                subtype,
+                param1,
+                param2,
+                param3,
+            } => (subtype, param1, param2, param3),
+        }
+    }
+}
+
+/// conditions of `Switch` entries
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    /// branch 0: flag not set, branch 1: flag set
+    Storyflag(u16),
+    /// branch 0: flag not set, branch 1: flag set
+    Sceneflag { scene: u16, flag: u16 },
+    /// one branch for every possible answer of the last text
+    Answer,
+    /// branch 0: less than count, branch 1: at least count
+    Rupees(u16),
+    /// branch 0: less than count, branch 1: at least count
+    Itemflag { itemflag: u16, count: u16 },
+    Raw {
+        subtype: u8,
+        param1: u16,
+        param2: u16,
+        param3: i16,
+    },
+}
+
+impl Condition {
+    pub fn from_raw(subtype: u8, param1: u16, param2: u16, param3: i16) -> Self {
+        match (subtype, param1, param2, param3) {
+            (6, 0, flag, 3) => Self::Storyflag(flag),
+            (6, scene, flag, 4) => Self::Sceneflag { scene, flag },
+            (6, 0, 0, 5) => Self::Answer,
+            (6, 0, rupees, 7) => Self::Rupees(rupees),
+            (6, count, itemflag, 8) => Self::Itemflag { itemflag, count },
+            _ => Self::Raw {
+                subtype,
+                param1,
+                param2,
+                param3,
+            },
+        }
+    }
+
+    /// returns subtype, param1, param2 and param3
+    pub fn to_raw(&self) -> (u8, u16, u16, i16) {
+        match *self {
+            Self::Storyflag(flag) => (6, 0, flag, 3),
+            Self::Sceneflag { scene, flag } => (6, scene, flag, 4),
+            Self::Answer => (6, 0, 0, 5),
+            Self::Rupees(rupees) => (6, 0, rupees, 7),
+            Self::Itemflag { itemflag, count } => (6, count, itemflag, 8),
+            Self::Raw {
+                subtype,
+                param1,
+                param2,
+                param3,
+            } => (subtype, param1, param2, param3),
+        }
+    }
+
+    /// how many branches a switch with this condition needs, None if it can have any number
+    pub fn branch_count(&self) -> Option<usize> {
+        match self {
+            Self::Storyflag(_)
+            | Self::Sceneflag { .. }
+            | Self::Rupees(_)
+            | Self::Itemflag { .. } => Some(2),
+            Self::Answer | Self::Raw { .. } => None,
+        }
+    }
+}
+
+/// `FlowEntry` with typed commands and conditions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TypedFlowEntry {
+    Start {
+        next: i16,
+    },
+    Text {
+        file: u16,
+        line: u16,
+        next: i16,
+    },
+    Command {
+        command: Command,
+        next: i16,
+    },
+    Switch {
+        condition: Condition,
+        branches: Vec<i16>,
+    },
+}
+
+impl From<FlowEntry> for TypedFlowEntry {
+    fn from(value: FlowEntry) -> Self {
+        match value {
+            FlowEntry::Start { next } => Self::Start { next },
+            FlowEntry::Text { file, line, next } => Self::Text { file, line, next },
+            FlowEntry::Flow {
+                subtype,
+                param1,
+                param2,
+                next,
+                param3,
+            } => Self::Command {
+                command: Command::from_raw(subtype, param1, param2, param3),
+                next,
+            },
+            FlowEntry::Switch {
+                subtype,
+                param1,
+                param2,
+                param3,
+                branches,
+            } => Self::Switch {
+                condition: Condition::from_raw(subtype, param1, param2, param3),
+                branches,
+            },
+        }
+    }
+}
+
+impl From<TypedFlowEntry> for FlowEntry {
+    fn from(value: TypedFlowEntry) -> Self {
+        match value {
+            TypedFlowEntry::Start { next } => Self::Start { next },
+            TypedFlowEntry::Text { file, line, next } => Self::Text { file, line, next },
+            TypedFlowEntry::Command { command, next } => {
+                let (subtype, param1, param2, param3) = command.to_raw();
+                Self::Flow {
+                    subtype,
+                    param1,
+                    param2,
+                    next,
+                    param3,
+                }
+            }
+            TypedFlowEntry::Switch {
+                condition,
+                branches,
+            } => {
+                let (subtype, param1, param2, param3) = condition.to_raw();
+                Self::Switch {
+                    subtype,
+                    param1,
+                    param2,
+                    param3,
+                    branches,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Command, Condition, TypedFlowEntry};
+    use crate::FlowEntry;
+
+    #[test]
+    pub fn test_conversion() {
+        let entries = vec![
+            FlowEntry::Flow {
+                subtype: 0,
+                param1: 0,
+                param2: 951,
+                next: 3,
+                param3: 0,
+            },
+            FlowEntry::Flow {
+                subtype: 0,
+                param1: 0,
+                param2: (-20i16) as u16,
+                next: 3,
+                param3: 8,
+            },
+            // storyflag command, but with an unexpected param1
+            FlowEntry::Flow {
+                subtype: 0,
+                param1: 1,
+                param2: 951,
+                next: 3,
+                param3: 0,
+            },
+            FlowEntry::Switch {
+                subtype: 6,
+                param1: 2,
+                param2: 500,
+                param3: 8,
+                branches: vec![1, 2],
+            },
+            FlowEntry::Switch {
+                subtype: 1,
+                param1: 2,
+                param2: 3,
+                param3: 4,
+                branches: vec![1, 2, 3],
+            },
+        ];
+        let typed: Vec<TypedFlowEntry> = entries.iter().cloned().map(Into::into).collect();
+        assert_eq!(
+            typed,
+            vec![
+                TypedFlowEntry::Command {
+                    command: Command::SetStoryflag(951),
+                    next: 3
+                },
+                TypedFlowEntry::Command {
+                    command: Command::AddRupees(-20),
+                    next: 3
+                },
+                TypedFlowEntry::Command {
+                    command: Command::Raw {
+                        subtype: 0,
+                        param1: 1,
+                        param2: 951,
+                        param3: 0
+                    },
+                    next: 3
+                },
+                TypedFlowEntry::Switch {
+                    condition: Condition::Itemflag {
+                        itemflag: 500,
+                        count: 2
+                    },
+                    branches: vec![1, 2]
+                },
+                TypedFlowEntry::Switch {
+                    condition: Condition::Raw {
+                        subtype: 1,
+                        param1: 2,
+                        param2: 3,
+                        param3: 4
+                    },
+                    branches: vec![1, 2, 3]
+                },
+            ]
+        );
+        let back: Vec<FlowEntry> = typed.into_iter().map(Into::into).collect();
+        assert_eq!(back, entries);
+    }
+}
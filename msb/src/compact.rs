@@ -0,0 +1,243 @@
+//! remove flows and text lines that are no longer used after editing
+
+use crate::{analysis::reachable, ExtraSection, FlowEntry, Msbf, Msbt};
+
+/// removes all flow entries that can't be reached from an entrypoint and renumbers the
+/// remaining ones, references that were already out of range are left as they are
+///
+/// returns the new index of every old entry, None if it was removed
+pub fn compact_flows(msbf: &mut Msbf) -> Vec<Option<usize>> {
+    let reachable = reachable(msbf);
+    let mapping = new_indices(&reachable);
+    let remap = |index: &mut i16| {
+        if let Some(Some(new_index)) = usize::try_from(*index).ok().and_then(|i| mapping.get(i)) {
+            *index = *new_index as i16;
+        }
+    };
+
+    let mut old_flows = std::mem::take(&mut msbf.flows).into_iter();
+    msbf.flows = reachable
+        .iter()
+        .filter_map(|&keep| {
+            let flow = old_flows.next().unwrap();
+            keep.then_some(flow)
+        })
+        .collect();
+    for flow in &mut msbf.flows {
+        match flow {
+            FlowEntry::Start { next }
+            | FlowEntry::Text { next, .. }
+            | FlowEntry::Flow { next, .. } => remap(next),
+            FlowEntry::Switch { branches, .. } => branches.iter_mut().for_each(remap),
+        }
+    }
+    for index in msbf.entrypoints.values_mut() {
+        // entrypoints that are in range are always reachable
+        if let Some(Some(new_index)) = mapping.get(*index as usize) {
+            *index = *new_index as u32;
+        }
+    }
+    mapping
+}
+
+/// removes all text lines that aren't shown by any `Text` entry and their labels,
+/// lines that have a label for which `keep_label` returns true are kept, because the game
+/// might look them up by name
+///
+/// returns the new index of every old line, None if it was removed
+pub fn compact_text(
+    msbf: &mut Msbf,
+    msbt: &mut Msbt,
+    keep_label: impl Fn(&str) -> bool,
+) -> Vec<Option<usize>> {
+    let mut used = vec![false; msbt.text.len()];
+    for flow in &msbf.flows {
+        if let FlowEntry::Text { line, .. } = flow {
+            if let Some(used) = used.get_mut(*line as usize) {
+                *used = true;
+            }
+        }
+    }
+    for (label, line) in &msbt.lbl {
+        if keep_label(label) {
+            if let Some(used) = used.get_mut(*line as usize) {
+                *used = true;
+            }
+        }
+    }
+    let mapping = new_indices(&used);
+
+    let mut old_text = std::mem::take(&mut msbt.text).into_iter();
+    msbt.text = used
+        .iter()
+        .filter_map(|&keep| {
+            let text = old_text.next().unwrap();
+            keep.then_some(text)
+        })
+        .collect();
+    for flow in &mut msbf.flows {
+        if let FlowEntry::Text { line, .. } = flow {
+            if let Some(Some(new_line)) = mapping.get(*line as usize) {
+                *line = *new_line as u16;
+            }
+        }
+    }
+    msbt.lbl
+        .retain(|_, line| match mapping.get(*line as usize) {
+            Some(Some(new_line)) => {
+                *line = *new_line as u32;
+                true
+            }
+            Some(None) => false,
+            // was already out of range
+            None => true,
+        });
+    let lbl = &msbt.lbl;
+    msbt.lbl_layout
+        .order
+        .retain(|label| lbl.contains_key(label));
+    for section in &mut msbt.extra_sections {
+        match section {
+            // values after the last text are kept
+            ExtraSection::Ato1(values) | ExtraSection::Tsy1(values) => {
+                let mut line = 0;
+                values.retain(|_| {
+                    line += 1;
+                    mapping.get(line - 1) != Some(&None)
+                });
+            }
+            ExtraSection::Nli1(pairs) => {
+                pairs.retain_mut(|(_, line)| match mapping.get(*line as usize) {
+                    Some(Some(new_line)) => {
+                        *line = *new_line as u32;
+                        true
+                    }
+                    Some(None) => false,
+                    None => true,
+                });
+            }
+            ExtraSection::Unknown { .. } => (),
+        }
+    }
+    mapping
+}
+
+fn new_indices(keep: &[bool]) -> Vec<Option<usize>> {
+    let mut next_index = 0;
+    keep.iter()
+        .map(|&keep| {
+            keep.then(|| {
+                next_index += 1;
+                next_index - 1
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{compact_flows, compact_text};
+    use crate::{ExtraSection, FlowEntry, Msbf, Msbt, TextSegment};
+
+    #[test]
+    pub fn test_compact() {
+        let mut msbf = Msbf {
+            flows: vec![
+                FlowEntry::Text {
+                    file: 0,
+                    line: 0,
+                    next: -1,
+                },
+                FlowEntry::Start { next: 3 },
+                FlowEntry::Text {
+                    file: 0,
+                    line: 1,
+                    next: -1,
+                },
+                FlowEntry::Switch {
+                    subtype: 6,
+                    param1: 0,
+                    param2: 5,
+                    param3: 3,
+                    branches: vec![4, -1],
+                },
+                FlowEntry::Text {
+                    file: 0,
+                    line: 2,
+                    next: 40,
+                },
+            ],
+            entrypoints: HashMap::from([("100_00".to_string(), 1)]),
+            ..Default::default()
+        };
+        let mut msbt = Msbt {
+            lbl: HashMap::from([
+                ("0".to_string(), 0),
+                ("1".to_string(), 1),
+                ("keep".to_string(), 1),
+                ("2".to_string(), 2),
+            ]),
+            text: vec![
+                TextSegment::default(),
+                TextSegment::default(),
+                TextSegment {
+                    atr: vec![],
+                    text: vec![2],
+                },
+            ],
+            extra_sections: vec![
+                ExtraSection::Tsy1(vec![10, 11, 12]),
+                ExtraSection::Nli1(vec![(5, 0), (6, 2)]),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            compact_flows(&mut msbf),
+            vec![None, Some(0), None, Some(1), Some(2)]
+        );
+        assert_eq!(
+            msbf.flows,
+            vec![
+                FlowEntry::Start { next: 1 },
+                FlowEntry::Switch {
+                    subtype: 6,
+                    param1: 0,
+                    param2: 5,
+                    param3: 3,
+                    branches: vec![2, -1],
+                },
+                FlowEntry::Text {
+                    file: 0,
+                    line: 2,
+                    next: 40,
+                },
+            ]
+        );
+        assert_eq!(msbf.entrypoints["100_00"], 0);
+
+        assert_eq!(
+            compact_text(&mut msbf, &mut msbt, |label| label == "keep"),
+            vec![None, Some(0), Some(1)]
+        );
+        assert_eq!(msbt.text[1].text, vec![2]);
+        assert!(matches!(msbf.flows[2], FlowEntry::Text { line: 1, .. }));
+        assert_eq!(
+            msbt.lbl,
+            HashMap::from([
+                ("1".to_string(), 0),
+                ("keep".to_string(), 0),
+                ("2".to_string(), 1),
+            ])
+        );
+        assert_eq!(
+            msbt.extra_sections,
+            vec![
+                ExtraSection::Tsy1(vec![11, 12]),
+                ExtraSection::Nli1(vec![(6, 1)]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! export flow graphs in the Graphviz DOT format
+
+use std::fmt::Write;
+
+use crate::{
+    command::{Command, Condition},
+    markup::to_markup,
+    text::parse_text,
+    FlowEntry, Msbf, Msbt,
+};
+
+/// texts longer than this are cut off in the node labels
+const TEXT_PREVIEW_LEN: usize = 40;
+
+/// renders the flow graph as DOT, with the entrypoints as root nodes
+///
+/// if the Msbt is given, text nodes show the start of their text
+pub fn to_dot(msbf: &Msbf, msbt: Option<&Msbt>) -> String {
+    let mut out = String::from("digraph msbf {\n");
+    let mut entrypoints: Vec<(&String, &u32)> = msbf.entrypoints.iter().collect();
+    entrypoints.sort_unstable();
+    for (name, index) in entrypoints {
+        let _ = writeln!(
+            out,
+            "    \"{0}\" [shape=box, style=bold];\n    \"{0}\" -> n{1};",
+            escape(name),
+            index
+        );
+    }
+    for (i, flow) in msbf.flows.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "    n{i} [label=\"{}\"];",
+            escape(&node_label(i, flow, msbt))
+        );
+        match flow {
+            FlowEntry::Start { next }
+            | FlowEntry::Text { next, .. }
+            | FlowEntry::Flow { next, .. } => {
+                if *next >= 0 {
+                    let _ = writeln!(out, "    n{i} -> n{next};");
+                }
+            }
+            FlowEntry::Switch { branches, .. } => {
+                for (branch, next) in branches.iter().enumerate() {
+                    if *next >= 0 {
+                        let _ = writeln!(out, "    n{i} -> n{next} [label=\"{branch}\"];");
+                    }
+                }
+            }
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+fn node_label(index: usize, flow: &FlowEntry, msbt: Option<&Msbt>) -> String {
+    let mut label = format!("{index}: {}", flow.get_type());
+    match flow {
+        FlowEntry::Start { .. } => {}
+        FlowEntry::Text { file, line, .. } => {
+            let _ = write!(label, " {file}/{line}");
+            if let Some(text) = msbt.and_then(|msbt| msbt.text.get(*line as usize)) {
+                let mut preview = to_markup(&parse_text(&text.text));
+                if let Some((cut, _)) = preview.char_indices().nth(TEXT_PREVIEW_LEN) {
+                    preview.truncate(cut);
+                    preview.push_str("...");
+                }
+                label.push('\n');
+                label.push_str(&preview);
+            }
+        }
+        FlowEntry::Flow {
+            subtype,
+            param1,
+            param2,
+            param3,
+            ..
+        } => {
+            let command = Command::from_raw(*subtype, *param1, *param2, *param3);
+            let _ = write!(label, "\n{command:?}");
+        }
+        FlowEntry::Switch {
+            subtype,
+            param1,
+            param2,
+            param3,
+            ..
+        } => {
+            let condition = Condition::from_raw(*subtype, *param1, *param2, *param3);
+            let _ = write!(label, "\n{condition:?}");
+        }
+    }
+    label
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::to_dot;
+    use crate::{FlowEntry, Msbf, Msbt, TextSegment};
+
+    #[test]
+    pub fn test_to_dot() {
+        let msbf = Msbf {
+            flows: vec![
+                FlowEntry::Start { next: 1 },
+                FlowEntry::Switch {
+                    subtype: 6,
+                    param1: 0,
+                    param2: 5,
+                    param3: 3,
+                    branches: vec![2, -1],
+                },
+                FlowEntry::Text {
+                    file: 0,
+                    line: 0,
+                    next: -1,
+                },
+            ],
+            entrypoints: HashMap::from([("100_00".to_string(), 0)]),
+            ..Default::default()
+        };
+        let msbt = Msbt {
+            text: vec![TextSegment {
+                atr: vec![],
+                text: "say \"hi\"".encode_utf16().collect(),
+            }],
+            ..Default::default()
+        };
+        let dot = to_dot(&msbf, Some(&msbt));
+        assert_eq!(
+            dot,
+            "digraph msbf {\n    \"100_00\" [shape=box, style=bold];\n    \"100_00\" -> n0;\n    \
+            n0 [label=\"0: Start\"];\n    n0 -> n1;\n    \
+            n1 [label=\"1: Switch\\nStoryflag(5)\"];\n    n1 -> n2 [label=\"0\"];\n    \
+            n2 [label=\"2: Text 0/0\\nsay \\\"hi\\\"\"];\n}\n"
+        );
+    }
+}
@@ -2,9 +2,17 @@
 //!
 //!
 
-use std::{borrow::Cow, collections::HashMap, mem::swap};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet},
+    mem::swap,
+};
 
-use crate::{FlowEntry, Msbf, Msbt, TextSegment};
+use crate::{
+    markup::{parse_markup, MarkupError},
+    text::write_text,
+    ExtraSection, FlowEntry, Msbf, Msbt, TextSegment,
+};
 
 #[derive(Debug, thiserror::Error)]
 pub enum FlowPatchError {
@@ -18,6 +26,23 @@ pub enum FlowPatchError {
     ExpectedSwitch,
     #[error("name not found: {0}")]
     NameNotFound(Cow<'static, str>),
+    #[error("flows after a jump can never be reached")]
+    UnreachableAfterJump,
+    #[error("invalid text: {0}")]
+    Markup(#[from] MarkupError),
+    #[error("invalid label: {0}")]
+    Label(#[from] LabelError),
+}
+
+/// a label that was used wrong while building a [`Flow`]
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum LabelError {
+    #[error("{0} doesn't label any entry")]
+    NoEntry(Cow<'static, str>),
+    #[error("{0} labels a jump, which isn't an entry")]
+    OnJump(Cow<'static, str>),
+    #[error("{0} is used more than once")]
+    Duplicate(Cow<'static, str>),
 }
 
 #[derive(Debug, Clone)]
@@ -164,7 +189,8 @@ impl RefFlowEntryNonDiverging {
 }
 
 pub mod flow {
-    use super::{Flowref, RefFlowEntry, RefFlowEntryNonDiverging};
+    use super::{Flowref, RefFlowEntryDiverging, RefFlowEntryNonDiverging};
+    use crate::command::{Command, Condition};
 
     pub fn start() -> RefFlowEntryNonDiverging {
         RefFlowEntryNonDiverging::Start {
@@ -172,24 +198,72 @@ pub mod flow {
         }
     }
 
+    pub fn command(command: Command) -> RefFlowEntryNonDiverging {
+        let (subtype, param1, param2, param3) = command.to_raw();
+        RefFlowEntryNonDiverging::Flow { subtype, param1, param2, next: Flowref::end(), param3 }
+    }
+
+    /// all branches point to the end
+    pub fn condition(condition: Condition, branch_count: usize) -> RefFlowEntryDiverging {
+        let (subtype, param1, param2, param3) = condition.to_raw();
+        RefFlowEntryDiverging { subtype, param1, param2, param3, branches: vec![Flowref::end(); branch_count] }
+    }
+
     pub fn set_storyflag(flag: u16) -> RefFlowEntryNonDiverging {
-        RefFlowEntryNonDiverging::Flow { subtype: 0, param1: 0, param2: flag, next: Flowref::end(), param3: 0 }
+        command(Command::SetStoryflag(flag))
     }
 
     pub fn unset_storyflag(flag: u16) -> RefFlowEntryNonDiverging {
-        RefFlowEntryNonDiverging::Flow { subtype: 0, param1: 0, param2: flag, next: Flowref::end(), param3: 1 }
+        command(Command::UnsetStoryflag(flag))
+    }
+
+    pub fn set_sceneflag(scene: u16, flag: u16) -> RefFlowEntryNonDiverging {
+        command(Command::SetSceneflag { scene, flag })
+    }
+
+    pub fn unset_sceneflag(scene: u16, flag: u16) -> RefFlowEntryNonDiverging {
+        command(Command::UnsetSceneflag { scene, flag })
     }
 
     pub fn add_rupees(rupees: i16) -> RefFlowEntryNonDiverging {
-        RefFlowEntryNonDiverging::Flow { subtype: 0, param1: 0, param2: rupees as u16, next: Flowref::end(), param3: 8 }
+        command(Command::AddRupees(rupees))
     }
 
     pub fn give_item(item: u16) -> RefFlowEntryNonDiverging {
-        RefFlowEntryNonDiverging::Flow { subtype: 0, param1: 0, param2: item, next: Flowref::end(), param3: 9 }
+        command(Command::GiveItem(item))
     }
 
     pub fn give_gratitude_crystals() -> RefFlowEntryNonDiverging {
-        RefFlowEntryNonDiverging::Flow { subtype: 0, param1: 0, param2: 0, next: Flowref::end(), param3: 43 }
+        command(Command::GiveGratitudeCrystals)
+    }
+
+    pub fn trigger_exit(roomid: u16, exit: u16) -> RefFlowEntryNonDiverging {
+        command(Command::TriggerExit { roomid, exit })
+    }
+
+    /// branch 0: flag not set, branch 1: flag set
+    pub fn check_storyflag(flag: u16) -> RefFlowEntryDiverging {
+        condition(Condition::Storyflag(flag), 2)
+    }
+
+    /// branch 0: flag not set, branch 1: flag set
+    pub fn check_sceneflag(scene: u16, flag: u16) -> RefFlowEntryDiverging {
+        condition(Condition::Sceneflag { scene, flag }, 2)
+    }
+
+    /// branch 0: less than count, branch 1: at least count
+    pub fn check_rupees(rupees: u16) -> RefFlowEntryDiverging {
+        condition(Condition::Rupees(rupees), 2)
+    }
+
+    /// branch 0: less than count, branch 1: at least count
+    pub fn check_itemflag(itemflag: u16, count: u16) -> RefFlowEntryDiverging {
+        condition(Condition::Itemflag { itemflag, count }, 2)
+    }
+
+    /// one branch for every possible answer of the last text
+    pub fn check_answer(answer_count: usize) -> RefFlowEntryDiverging {
+        condition(Condition::Answer, answer_count)
     }
 }
 
@@ -199,73 +273,242 @@ pub struct EventPatcher {
     ref_flows: Vec<RefFlowEntry>,
     // store here when adding new flow entries
     index_names: HashMap<Cow<'static, str>, i16>,
+    // entrypoints added while patching, resolved in finish
+    new_entrypoints: Vec<(String, Flowref)>,
 }
 
-pub struct Flow;
-pub struct Flowend;
+/// a sequence of flow entries that is turned into actual entries by
+/// [`EventPatcher::add_flows`] or [`EventPatcher::create_entrypoint`]
+///
+/// every entry continues with the next one in the sequence, the branches of a switch
+/// continue after the switch once they are done, unless they jump somewhere else
+#[derive(Debug, Clone, Default)]
+pub struct Flow {
+    steps: Vec<FlowStep>,
+    // reported when the flow is added
+    label_errors: Vec<LabelError>,
+}
 
-impl From<Flow> for Flowend {
-    fn from(value: Flow) -> Self {
-        Flowend
-    }
+#[derive(Debug, Clone)]
+struct FlowStep {
+    label: Option<Cow<'static, str>>,
+    kind: FlowStepKind,
+}
+
+#[derive(Debug, Clone)]
+enum FlowStepKind {
+    Text(String),
+    Action(RefFlowEntryNonDiverging),
+    Switch {
+        switch: RefFlowEntryDiverging,
+        branches: Vec<Flow>,
+    },
+    Jump(Flowref),
 }
 
 impl Flow {
-    pub fn with_label(self, label: impl Into<Cow<'static, str>>) -> Flow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn push(mut self, kind: FlowStepKind) -> Self {
+        self.steps.push(FlowStep { label: None, kind });
+        self
+    }
+
+    /// gives the last entry a name, that can be used to jump to it
+    ///
+    /// labels on nothing or on a jump are reported when the flow is added
+    pub fn with_label(mut self, label: impl Into<Cow<'static, str>>) -> Flow {
+        let label = label.into();
+        match self.steps.last_mut() {
+            Some(FlowStep {
+                kind: FlowStepKind::Jump(_),
+                ..
+            }) => self.label_errors.push(LabelError::OnJump(label)),
+            Some(step) => step.label = Some(label),
+            None => self.label_errors.push(LabelError::NoEntry(label)),
+        }
         self
     }
 
+    /// appends all entries of the other sequence
+    pub fn then(mut self, other: Flow) -> Flow {
+        self.steps.extend(other.steps);
+        self.label_errors.extend(other.label_errors);
+        self
+    }
+
+    // collects the labels of all entries, including the ones in branches
+    fn collect_labels<'f>(&'f self, labels: &mut Vec<&'f Cow<'static, str>>) -> Result<(), LabelError> {
+        if let Some(error) = self.label_errors.first() {
+            return Err(error.clone());
+        }
+        for step in &self.steps {
+            labels.extend(&step.label);
+            if let FlowStepKind::Switch { branches, .. } = &step.kind {
+                for branch in branches {
+                    branch.collect_labels(labels)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// appends any non diverging entry, its next is overwritten
+    pub fn action(self, entry: RefFlowEntryNonDiverging) -> Flow {
+        self.push(FlowStepKind::Action(entry))
+    }
+
+    /// appends any switch, its branches are replaced by the given flows
+    pub fn switch(self, switch: RefFlowEntryDiverging, branches: Vec<Flow>) -> Flow {
+        self.push(FlowStepKind::Switch { switch, branches })
+    }
+
     pub fn give_item(self, item: u16) -> Flow {
-        Flow
+        self.action(flow::give_item(item))
     }
 
-    pub fn check_itemflag(self, itemflag: u16, count: u16) -> Flow {
-        Flow
+    pub fn check_itemflag(self, itemflag: u16, count: u16, enough: &[Flow], not_enough: &[Flow]) -> Flow {
+        self.switch(
+            flow::check_itemflag(itemflag, count),
+            vec![Flow::concat(not_enough), Flow::concat(enough)],
+        )
     }
 
+    /// the text is written in markup, see [`crate::markup`]
     pub fn speak_text(self, text: &str) -> Flow {
-        Flow
+        self.push(FlowStepKind::Text(text.to_string()))
     }
 
     pub fn trigger_exit(self, roomid: u16, exit: u16) -> Flow {
-        Flow
+        self.action(flow::trigger_exit(roomid, exit))
     }
 
     pub fn set_storyflag(self, storyflag: u16) -> Flow {
-        Flow
+        self.action(flow::set_storyflag(storyflag))
+    }
+
+    pub fn unset_storyflag(self, storyflag: u16) -> Flow {
+        self.action(flow::unset_storyflag(storyflag))
+    }
+
+    pub fn check_storyflag(self, flag: u16, setflows: &[Flow], notsetflows: &[Flow]) -> Flow {
+        self.switch(
+            flow::check_storyflag(flag),
+            vec![Flow::concat(notsetflows), Flow::concat(setflows)],
+        )
+    }
+
+    /// branches on the answer to the last text, which needs 2 choices
+    pub fn check_answer2(self, flows1: &[Flow], flows2: &[Flow]) -> Flow {
+        self.switch(
+            flow::check_answer(2),
+            vec![Flow::concat(flows1), Flow::concat(flows2)],
+        )
+    }
+
+    /// branches on the answer to the last text, which needs 3 choices
+    pub fn check_answer3(self, flows1: &[Flow], flows2: &[Flow], flows3: &[Flow]) -> Flow {
+        self.switch(
+            flow::check_answer(3),
+            vec![Flow::concat(flows1), Flow::concat(flows2), Flow::concat(flows3)],
+        )
+    }
+
+    /// continues somewhere else, nothing can come after this
+    pub fn jump(self, dest: impl Into<Flowref>) -> Flow {
+        self.push(FlowStepKind::Jump(dest.into()))
+    }
+
+    fn concat(flows: &[Flow]) -> Flow {
+        flows.iter().cloned().fold(Flow::new(), Flow::then)
+    }
+}
+
+/// shorthands to start a [`Flow`], so that events can be written as nested expressions:
+///
+/// ```
+/// use msb::edit::dsl::*;
+///
+/// let flows = [
+///     speak_text("choice:\n[1] yes\nor no[2-]").with_label("choice"),
+///     check_answer2(&[
+///         speak_text("cool, you chose yes"),
+///         set_storyflag(899),
+///         jump("choice"),
+///     ], &[
+///         check_storyflag(899, &[trigger_exit(0, 0)], &[trigger_exit(0, 1)]),
+///     ]),
+/// ];
+/// ```
+pub mod dsl {
+    use super::{Flow, Flowref};
+
+    pub fn speak_text(text: &str) -> Flow {
+        Flow::new().speak_text(text)
+    }
+
+    pub fn give_item(item: u16) -> Flow {
+        Flow::new().give_item(item)
+    }
+
+    pub fn set_storyflag(storyflag: u16) -> Flow {
+        Flow::new().set_storyflag(storyflag)
+    }
+
+    pub fn unset_storyflag(storyflag: u16) -> Flow {
+        Flow::new().unset_storyflag(storyflag)
     }
 
-    pub fn check_storyflag(self, flag: u16, setflows: &[Flow], notsetflows: &[Flow]) -> Flowend {
-        Flowend
+    pub fn trigger_exit(roomid: u16, exit: u16) -> Flow {
+        Flow::new().trigger_exit(roomid, exit)
     }
 
-    pub fn check_answer2(self, flows1: &[Flow], flows2: &[Flow]) -> Flowend {
-        Flowend
+    pub fn check_storyflag(flag: u16, setflows: &[Flow], notsetflows: &[Flow]) -> Flow {
+        Flow::new().check_storyflag(flag, setflows, notsetflows)
     }
 
-    pub fn jump(self, dest: impl Into<Flowref>) -> Flowend {
-        Flowend
+    pub fn check_itemflag(itemflag: u16, count: u16, enough: &[Flow], not_enough: &[Flow]) -> Flow {
+        Flow::new().check_itemflag(itemflag, count, enough, not_enough)
+    }
+
+    pub fn check_answer2(flows1: &[Flow], flows2: &[Flow]) -> Flow {
+        Flow::new().check_answer2(flows1, flows2)
+    }
+
+    pub fn check_answer3(flows1: &[Flow], flows2: &[Flow], flows3: &[Flow]) -> Flow {
+        Flow::new().check_answer3(flows1, flows2, flows3)
+    }
+
+    pub fn jump(dest: impl Into<Flowref>) -> Flow {
+        Flow::new().jump(dest)
     }
 }
 
-// pub fn test() {
-//     create_entrypoint("117_99", &[
-//         speak_text("hi, this is a non functional test"),
-//         give_item(0),
-//         speak_text("choice:\n[1] yes\nor no[2-]").with_label("choice"),
-//         check_answer2(&[
-//             speak_text("cool, you chose yes, don't"),
-//             set_storyflag(899),
-//             jump("choice"),
-//         ], &[
-//             check_storyflag(899, &[
-//                 trigger_exit(0, 0)
-//             ], &[
-//                 trigger_exit(0, 1)
-//             ])
-//         ])
-//     ])
-// }
+// a jump has to be the last step, and all texts have to be valid markup
+fn check_steps(steps: &[&FlowStep]) -> Result<(), FlowPatchError> {
+    if let Some(pos) = steps.iter().position(|s| matches!(s.kind, FlowStepKind::Jump(_))) {
+        if pos != steps.len() - 1 {
+            return Err(FlowPatchError::UnreachableAfterJump);
+        }
+    }
+    for step in steps {
+        match &step.kind {
+            FlowStepKind::Text(text) => {
+                parse_markup(text)?;
+            }
+            FlowStepKind::Switch { branches, .. } => {
+                for branch in branches {
+                    let steps: Vec<&FlowStep> = branch.steps.iter().collect();
+                    check_steps(&steps)?;
+                }
+            }
+            FlowStepKind::Action(_) | FlowStepKind::Jump(_) => {}
+        }
+    }
+    Ok(())
+}
 
 impl EventPatcher {
     pub fn new(mut flow: Msbf, text: Msbt) -> Self {
@@ -280,6 +523,7 @@ impl EventPatcher {
             text,
             ref_flows,
             index_names: Default::default(),
+            new_entrypoints: Vec::new(),
         }
     }
     pub fn get_flow(
@@ -326,36 +570,49 @@ impl EventPatcher {
             _ => Err(FlowPatchError::ExpectedSwitch),
         }
     }
-    pub fn add_show_text(&mut self, text: &str, next_flow: impl Into<Flowref>) -> i16 {
-        let text_line = self.text.text.len() as u16;
-        let flow_index = self.ref_flows.len();
-
-        self.text.text.push(TextSegment {
-            atr: vec![0, 0],
-            text: text.encode_utf16().collect(),
-        });
+    // adds a text line, labelled with its own line number
+    fn add_text_line(&mut self, text: Vec<u16>) -> u16 {
+        let text_line = self.push_text_segment(text);
         self.text
             .lbl
             .insert(format!("{text_line}"), text_line.into());
+        text_line
+    }
+    fn add_show_line(&mut self, text: Vec<u16>, next_flow: Flowref) -> i16 {
+        let flow_index = self.ref_flows.len();
+        let text_line = self.add_text_line(text);
         self.ref_flows.push(RefFlowEntryNonDiverging::Text {
             file: 0,
             line: text_line,
-            next: next_flow.into(),
+            next: next_flow,
         }.into());
 
-        return flow_index as i16;
+        flow_index as i16
+    }
+    /// the text is used as is, use [`Self::add_show_markup`] for control sequences
+    pub fn add_show_text(&mut self, text: &str, next_flow: impl Into<Flowref>) -> i16 {
+        self.add_show_line(text.encode_utf16().collect(), next_flow.into())
     }
+    /// the text is written in markup, see [`crate::markup`]
+    pub fn add_show_markup(&mut self, markup: &str, next_flow: impl Into<Flowref>) -> Result<i16, FlowPatchError> {
+        let text = write_text(&parse_markup(markup)?);
+        Ok(self.add_show_line(text, next_flow.into()))
+    }
+    /// the text is used as is, use [`Self::add_markup_with_label`] for control sequences
     pub fn add_text_with_label(&mut self, text: &str, label: impl Into<String>) -> u16 {
         let label = label.into();
-        let text_line = self.text.text.len() as u16;
-
-        self.text.text.push(TextSegment {
-            atr: vec![0, 0],
-            text: text.encode_utf16().collect(),
-        });
+        let text_line = self.push_text_segment(text.encode_utf16().collect());
         self.text.lbl.insert(label, text_line.into());
 
-        return text_line;
+        text_line
+    }
+    /// the text is written in markup, see [`crate::markup`]
+    pub fn add_markup_with_label(&mut self, markup: &str, label: impl Into<String>) -> Result<u16, FlowPatchError> {
+        let text = write_text(&parse_markup(markup)?);
+        let text_line = self.push_text_segment(text);
+        self.text.lbl.insert(label.into(), text_line.into());
+
+        Ok(text_line)
     }
     pub fn add_flow(&mut self, flow: RefFlowEntry, label: Option<impl Into<Cow<'static, str>>>) -> i16 {
         let next_index: i16 = self.ref_flows.len().try_into().unwrap();
@@ -365,4 +622,404 @@ impl EventPatcher {
         self.ref_flows.push(flow);
         next_index
     }
+    /// adds all entries of the flows, after the last one the event continues with next
+    ///
+    /// returns where the added flows start
+    pub fn add_flows(&mut self, flows: &[Flow], next: impl Into<Flowref>) -> Result<Flowref, FlowPatchError> {
+        // check the labels first, so that nothing is added if they are wrong
+        let mut labels = Vec::new();
+        for flow in flows {
+            flow.collect_labels(&mut labels)?;
+        }
+        let mut seen = HashSet::new();
+        for label in labels {
+            if self.index_names.contains_key(label) || !seen.insert(label) {
+                return Err(LabelError::Duplicate(label.clone()).into());
+            }
+        }
+        let steps: Vec<&FlowStep> = flows.iter().flat_map(|f| f.steps.iter()).collect();
+        // same for the jumps and texts, adding can't fail after this
+        check_steps(&steps)?;
+        self.add_steps(&steps, next.into())
+    }
+    // entries are added from the back, so that every entry already knows its next
+    fn add_steps(&mut self, steps: &[&FlowStep], continuation: Flowref) -> Result<Flowref, FlowPatchError> {
+        let mut next = continuation;
+        for step in steps.iter().rev() {
+            let label = step.label.clone();
+            next = match &step.kind {
+                FlowStepKind::Jump(dest) => dest.clone(),
+                FlowStepKind::Text(text) => {
+                    let line = self.add_text_line(write_text(&parse_markup(text)?));
+                    let entry = RefFlowEntryNonDiverging::Text { file: 0, line, next };
+                    self.add_flow(entry.into(), label).into()
+                }
+                FlowStepKind::Action(entry) => {
+                    let entry = entry.clone().with_next(next);
+                    self.add_flow(entry.into(), label).into()
+                }
+                FlowStepKind::Switch { switch, branches } => {
+                    let mut switch = switch.clone();
+                    switch.branches = branches
+                        .iter()
+                        .map(|branch| {
+                            let steps: Vec<&FlowStep> = branch.steps.iter().collect();
+                            self.add_steps(&steps, next.clone())
+                        })
+                        .collect::<Result<_, _>>()?;
+                    self.add_flow(switch.into(), label).into()
+                }
+            };
+        }
+        Ok(next)
+    }
+    /// adds the flows and a new FEN1 entrypoint that starts them
+    ///
+    /// returns the index of the start entry
+    pub fn create_entrypoint(&mut self, name: impl Into<String>, flows: &[Flow]) -> Result<i16, FlowPatchError> {
+        let first = self.add_flows(flows, Flowref::end())?;
+        let start = self.add_flow(RefFlowEntryNonDiverging::Start { next: first }.into(), None::<&'static str>);
+        self.add_entrypoint(name, start);
+        Ok(start)
+    }
+    /// adds a new FEN1 entrypoint, the flow can also be a name that is only added later
+    pub fn add_entrypoint(&mut self, name: impl Into<String>, flow: impl Into<Flowref>) {
+        self.new_entrypoints.push((name.into(), flow.into()));
+    }
+    // all texts need the same attribute length, new ones get zeroed attributes, and
+    // sections with a value per message get a 0 for the new line, returns the line number
+    fn push_text_segment(&mut self, text: Vec<u16>) -> u16 {
+        let text_line = self.text.text.len() as u16;
+        let atr_len = self.text.text.first().map_or(0, |t| t.atr.len());
+        self.text.text.push(TextSegment {
+            atr: vec![0; atr_len],
+            text,
+        });
+        for section in &mut self.text.extra_sections {
+            if let ExtraSection::Ato1(values) | ExtraSection::Tsy1(values) = section {
+                values.push(0);
+            }
+        }
+        text_line
+    }
+    /// resolves all names and puts the (possibly edited) flows back, the result can
+    /// be written with `write_msbf` and `write_msbt`
+    pub fn finish(self) -> Result<(Msbf, Msbt), FlowPatchError> {
+        let Self {
+            mut flow,
+            text,
+            ref_flows,
+            index_names,
+            new_entrypoints,
+        } = self;
+        let flow_count = ref_flows.len();
+        let resolve = |flowref: Flowref| -> Result<i16, FlowPatchError> {
+            let index = match flowref {
+                Flowref::Index(index) => index,
+                Flowref::Name(name) => *index_names
+                    .get(&name)
+                    .ok_or(FlowPatchError::NameNotFound(name))?,
+            };
+            // -1 means the event ends here
+            if index < -1 || index as isize >= flow_count as isize {
+                return Err(FlowPatchError::OutOfRange(index));
+            }
+            Ok(index)
+        };
+        let mut flows = Vec::with_capacity(flow_count);
+        for ref_flow in ref_flows {
+            flows.push(match ref_flow {
+                RefFlowEntry::NonDiverging(RefFlowEntryNonDiverging::Start { next }) => {
+                    FlowEntry::Start {
+                        next: resolve(next)?,
+                    }
+                }
+                RefFlowEntry::NonDiverging(RefFlowEntryNonDiverging::Text { file, line, next }) => {
+                    FlowEntry::Text {
+                        file,
+                        line,
+                        next: resolve(next)?,
+                    }
+                }
+                RefFlowEntry::NonDiverging(RefFlowEntryNonDiverging::Flow {
+                    subtype,
+                    param1,
+                    param2,
+                    next,
+                    param3,
+                }) => FlowEntry::Flow {
+                    subtype,
+                    param1,
+                    param2,
+                    next: resolve(next)?,
+                    param3,
+                },
+                RefFlowEntry::Diverging(RefFlowEntryDiverging {
+                    subtype,
+                    param1,
+                    param2,
+                    param3,
+                    branches,
+                }) => FlowEntry::Switch {
+                    subtype,
+                    param1,
+                    param2,
+                    param3,
+                    branches: branches
+                        .into_iter()
+                        .map(resolve)
+                        .collect::<Result<_, _>>()?,
+                },
+            });
+        }
+        for (name, flowref) in new_entrypoints {
+            let index = resolve(flowref)?;
+            // an entrypoint has to point to an actual flow
+            let index = u32::try_from(index).map_err(|_| FlowPatchError::OutOfRange(index))?;
+            flow.entrypoints.insert(name, index);
+        }
+        flow.flows = flows;
+        Ok((flow, text))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, io::Cursor};
+
+    use super::{flow, EventPatcher, Flow, FlowPatchError, Flowref, LabelError};
+    use crate::{parse_msbf, parse_msbt, ExtraSection, FlowEntry, Msbf, Msbt, TextSegment};
+
+    fn test_patcher() -> EventPatcher {
+        let msbf = Msbf {
+            flows: vec![
+                FlowEntry::Start { next: 1 },
+                FlowEntry::Text {
+                    file: 0,
+                    line: 0,
+                    next: -1,
+                },
+            ],
+            entrypoints: HashMap::from([("100_00".to_string(), 0)]),
+            ..Default::default()
+        };
+        let msbt = Msbt {
+            lbl: HashMap::from([("100_00".to_string(), 0)]),
+            text: vec![TextSegment {
+                atr: vec![1, 2, 3],
+                text: "hello".encode_utf16().collect(),
+            }],
+            extra_sections: vec![ExtraSection::Tsy1(vec![4])],
+            ..Default::default()
+        };
+        EventPatcher::new(msbf, msbt)
+    }
+
+    #[test]
+    pub fn test_finish() {
+        let mut patcher = test_patcher();
+        patcher.add_flow(flow::set_storyflag(5).into(), Some("flag"));
+        patcher.set_next(1, "flag").unwrap();
+        let text_flow = patcher.add_show_markup("new <red>text</red>", Flowref::end()).unwrap();
+        patcher.set_next("flag", text_flow).unwrap();
+        patcher.add_entrypoint("100_01", "flag");
+
+        let (msbf, msbt) = patcher.finish().unwrap();
+        assert!(matches!(msbf.flows[1], FlowEntry::Text { next: 2, .. }));
+        assert!(matches!(msbf.flows[2], FlowEntry::Flow { next: 3, .. }));
+        assert!(matches!(msbf.flows[3], FlowEntry::Text { line: 1, next: -1, .. }));
+        assert_eq!(msbf.entrypoints["100_01"], 2);
+        // new texts get the same attribute length
+        assert_eq!(msbt.text[1].atr, vec![0, 0, 0]);
+        assert_eq!(msbt.text[1].markup(), "new <red>text</red>");
+        assert_eq!(msbt.extra_sections, vec![ExtraSection::Tsy1(vec![4, 0])]);
+
+        let mut msbf_buf = Vec::new();
+        msbf.write_msbf(&mut Cursor::new(&mut msbf_buf)).unwrap();
+        let reparsed = parse_msbf(&mut Cursor::new(&msbf_buf)).unwrap();
+        assert_eq!(reparsed.flows.len(), 4);
+        assert_eq!(reparsed.entrypoints, msbf.entrypoints);
+
+        let mut msbt_buf = Vec::new();
+        msbt.write_msbt(&mut Cursor::new(&mut msbt_buf)).unwrap();
+        let reparsed = parse_msbt(&mut Cursor::new(&msbt_buf)).unwrap();
+        assert_eq!(reparsed.text[1].text, msbt.text[1].text);
+    }
+
+    #[test]
+    pub fn test_plain_text() {
+        let mut patcher = test_patcher();
+        let text_flow = patcher.add_show_text("<red>", Flowref::end());
+        let plain_line = patcher.add_text_with_label("a\\b", "plain");
+        let markup_line = patcher.add_markup_with_label("a\\b", "markup").unwrap();
+        assert!(matches!(
+            patcher.add_markup_with_label("<red", "invalid"),
+            Err(FlowPatchError::Markup(_))
+        ));
+
+        let (msbf, msbt) = patcher.finish().unwrap();
+        let FlowEntry::Text { line, .. } = msbf.flows[text_flow as usize] else {
+            panic!("expected text");
+        };
+        // no control sequences, the text stays as it is
+        assert_eq!(String::from_utf16(&msbt.text[line as usize].text).unwrap(), "<red>");
+        assert_eq!(String::from_utf16(&msbt.text[plain_line as usize].text).unwrap(), "a\\b");
+        // the markup escape is removed
+        assert_eq!(String::from_utf16(&msbt.text[markup_line as usize].text).unwrap(), "ab");
+        assert_eq!(msbt.lbl["plain"], u32::from(plain_line));
+        assert_eq!(msbt.lbl["markup"], u32::from(markup_line));
+        assert!(!msbt.lbl.contains_key("invalid"));
+    }
+
+    #[test]
+    pub fn test_create_entrypoint() {
+        use super::dsl::*;
+
+        let mut patcher = test_patcher();
+        let start = patcher
+            .create_entrypoint(
+                "117_99",
+                &[
+                    speak_text("hi"),
+                    give_item(0),
+                    speak_text("choice:\n[1] yes\nor no[2-]").with_label("choice"),
+                    check_answer2(
+                        &[speak_text("yes"), set_storyflag(899), jump("choice")],
+                        &[check_storyflag(
+                            899,
+                            &[trigger_exit(0, 0)],
+                            &[trigger_exit(0, 1)],
+                        )],
+                    ),
+                    speak_text("bye"),
+                ],
+            )
+            .unwrap();
+        let (msbf, msbt) = patcher.finish().unwrap();
+        assert_eq!(msbf.entrypoints["117_99"], start as u32);
+
+        // follow the event, mostly taking the first branch
+        let text_at = |index: i16| match &msbf.flows[index as usize] {
+            FlowEntry::Text { line, next, .. } => {
+                (String::from_utf16(&msbt.text[*line as usize].text).unwrap(), *next)
+            }
+            other => panic!("expected text, got {other:?}"),
+        };
+        let FlowEntry::Start { next } = msbf.flows[start as usize] else {
+            panic!("expected start");
+        };
+        let (text, next) = text_at(next);
+        assert_eq!(text, "hi");
+        let FlowEntry::Flow { param3: 9, next, .. } = msbf.flows[next as usize] else {
+            panic!("expected give item");
+        };
+        let choice = next;
+        let (text, next) = text_at(choice);
+        assert_eq!(text, "choice:\n[1] yes\nor no[2-]");
+        let FlowEntry::Switch { branches, .. } = &msbf.flows[next as usize] else {
+            panic!("expected switch");
+        };
+        let (text, next) = text_at(branches[0]);
+        assert_eq!(text, "yes");
+        let FlowEntry::Flow { param2: 899, next, .. } = msbf.flows[next as usize] else {
+            panic!("expected set storyflag");
+        };
+        // jumps back to the text with the choice
+        assert_eq!(next, choice);
+        // the other branch ends up after the switch
+        let FlowEntry::Switch { branches, .. } = &msbf.flows[branches[1] as usize] else {
+            panic!("expected switch");
+        };
+        let FlowEntry::Flow { next, .. } = msbf.flows[branches[0] as usize] else {
+            panic!("expected exit");
+        };
+        assert_eq!(text_at(next), ("bye".to_string(), -1));
+    }
+
+    #[test]
+    pub fn test_label_errors() {
+        use super::dsl::*;
+
+        let mut patcher = test_patcher();
+        let flows = [speak_text("a").with_label("a"), jump("a").with_label("b")];
+        assert!(matches!(
+            patcher.add_flows(&flows, Flowref::end()),
+            Err(FlowPatchError::Label(LabelError::OnJump(name))) if name == "b"
+        ));
+        let flows = [check_storyflag(1, &[Flow::new().with_label("c")], &[])];
+        assert!(matches!(
+            patcher.add_flows(&flows, Flowref::end()),
+            Err(FlowPatchError::Label(LabelError::NoEntry(name))) if name == "c"
+        ));
+        let flows = [
+            speak_text("a").with_label("a"),
+            check_storyflag(1, &[speak_text("b").with_label("a")], &[]),
+        ];
+        assert!(matches!(
+            patcher.add_flows(&flows, Flowref::end()),
+            Err(FlowPatchError::Label(LabelError::Duplicate(name))) if name == "a"
+        ));
+        // nothing was added by the failed calls
+        assert_eq!(patcher.finish().unwrap().0.flows.len(), 2);
+
+        let mut patcher = test_patcher();
+        patcher
+            .add_flows(&[speak_text("a").with_label("a")], Flowref::end())
+            .unwrap();
+        assert!(matches!(
+            patcher.add_flows(&[speak_text("b").with_label("a")], Flowref::end()),
+            Err(FlowPatchError::Label(LabelError::Duplicate(_)))
+        ));
+    }
+
+    #[test]
+    pub fn test_add_flows_atomic() {
+        use super::dsl::*;
+
+        let mut patcher = test_patcher();
+        // the invalid text is only reached after other entries would have been added
+        let flows = [
+            speak_text("a").with_label("a"),
+            check_storyflag(1, &[set_storyflag(2)], &[speak_text("b")]),
+            speak_text("<unknown>"),
+        ];
+        assert!(matches!(
+            patcher.add_flows(&flows, Flowref::end()),
+            Err(FlowPatchError::Markup(_))
+        ));
+        let flows = [
+            speak_text("a"),
+            check_storyflag(1, &[jump("a"), set_storyflag(2)], &[]),
+        ];
+        assert!(matches!(
+            patcher.add_flows(&flows, Flowref::end()),
+            Err(FlowPatchError::UnreachableAfterJump)
+        ));
+        // the label of the failed call can still be used
+        patcher
+            .add_flows(&[speak_text("a").with_label("a")], Flowref::end())
+            .unwrap();
+        let (msbf, msbt) = patcher.finish().unwrap();
+        assert_eq!(msbf.flows.len(), 3);
+        assert_eq!(msbt.text.len(), 2);
+        assert_eq!(msbt.lbl.len(), 2);
+        assert_eq!(msbt.extra_sections, vec![ExtraSection::Tsy1(vec![4, 0])]);
+    }
+
+    #[test]
+    pub fn test_finish_errors() {
+        let mut patcher = test_patcher();
+        patcher.set_next(1, "missing").unwrap();
+        assert!(matches!(
+            patcher.finish(),
+            Err(FlowPatchError::NameNotFound(name)) if name == "missing"
+        ));
+
+        let mut patcher = test_patcher();
+        patcher.set_next(1, 10).unwrap();
+        assert!(matches!(
+            patcher.finish(),
+            Err(FlowPatchError::OutOfRange(10))
+        ));
+    }
 }
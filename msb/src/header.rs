@@ -0,0 +1,121 @@
+use std::io::{Read, Seek, SeekFrom, Write};
+
+use binrw::{BinReaderExt, BinWriterExt, Endian};
+use serde::{Deserialize, Serialize};
+
+use crate::MsbError;
+
+/// encoding of the TXT2 strings, the values in control sequences are u16 in every encoding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TextEncoding {
+    Utf8,
+    Utf16,
+    Utf32,
+}
+
+impl TextEncoding {
+    pub fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(Self::Utf8),
+            1 => Some(Self::Utf16),
+            2 => Some(Self::Utf32),
+            _ => None,
+        }
+    }
+
+    pub fn to_byte(self) -> u8 {
+        match self {
+            Self::Utf8 => 0,
+            Self::Utf16 => 1,
+            Self::Utf32 => 2,
+        }
+    }
+
+    /// size of a single code unit in bytes
+    pub fn unit_size(self) -> u32 {
+        match self {
+            Self::Utf8 => 1,
+            Self::Utf16 => 2,
+            Self::Utf32 => 4,
+        }
+    }
+}
+
+/// the fields of the 0x20 byte file header
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MsbHeader {
+    /// byte order of the whole file, from the byte order mark
+    #[serde(with = "crate::serialize::EndianDef")]
+    pub endian: Endian,
+    /// bytes 0x0A-0x0B, zero in all known files but written back as they were read
+    #[serde(default)]
+    pub reserved: [u8; 2],
+    pub encoding: TextEncoding,
+    pub version: u8,
+    /// section count of the original file, written files always have the actual count
+    pub section_count: u16,
+}
+
+impl MsbHeader {
+    pub fn msbt() -> Self {
+        Self {
+            endian: Endian::Big,
+            reserved: [0; 2],
+            encoding: TextEncoding::Utf16,
+            version: 3,
+            section_count: 3,
+        }
+    }
+
+    pub fn msbf() -> Self {
+        Self {
+            endian: Endian::Big,
+            reserved: [0; 2],
+            encoding: TextEncoding::Utf8,
+            version: 3,
+            section_count: 2,
+        }
+    }
+
+    pub(crate) fn read<R: Read + Seek>(r: &mut R) -> Result<Self, MsbError> {
+        r.seek(SeekFrom::Start(8))?;
+        let mut bom = [0u8; 2];
+        r.read_exact(&mut bom)?;
+        let endian = match bom {
+            [0xFE, 0xFF] => Endian::Big,
+            [0xFF, 0xFE] => Endian::Little,
+            _ => return Err(MsbError::UnknownByteOrderMark(bom)),
+        };
+        let mut reserved = [0u8; 2];
+        r.read_exact(&mut reserved)?;
+        let encoding_byte = r.read_be::<u8>()?;
+        let encoding = TextEncoding::from_byte(encoding_byte)
+            .ok_or(MsbError::UnknownEncoding(encoding_byte))?;
+        let version = r.read_be::<u8>()?;
+        let section_count = r.read_type::<u16>(endian)?;
+        Ok(Self {
+            endian,
+            reserved,
+            encoding,
+            version,
+            section_count,
+        })
+    }
+
+    /// writes the whole header, the file size is filled in later
+    pub(crate) fn write<WS: Write + Seek>(
+        &self,
+        ws: &mut WS,
+        magic: &[u8; 8],
+        section_count: u16,
+    ) -> Result<(), MsbError> {
+        ws.write_all(magic)?;
+        ws.write_type(&0xFEFFu16, self.endian)?;
+        ws.write_all(&self.reserved)?;
+        ws.write_all(&[self.encoding.to_byte(), self.version])?;
+        ws.write_type(&section_count, self.endian)?;
+        ws.write_all(&[0u8; 16])?;
+        Ok(())
+    }
+}
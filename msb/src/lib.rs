@@ -1,17 +1,68 @@
-use binrw::{BinReaderExt, BinWriterExt};
+use binrw::{BinReaderExt, BinWriterExt, Endian};
 use encoding::all::WINDOWS_31J;
 use encoding::{DecoderTrap, Encoding};
+use serde::{Deserialize, Serialize};
 use std::io::Write;
 use std::ops::{BitAnd, Not};
 use std::{
     collections::HashMap,
-    io::{Read, Seek, SeekFrom},
+    io::{Cursor, Read, Seek, SeekFrom},
     ops::Neg,
 };
 use structs::RawFlw3;
+use text::{TextPart, CONTROL_END, CONTROL_START};
 
+pub mod analysis;
+pub mod command;
+pub mod compact;
+pub mod dot;
 pub mod edit;
+mod header;
+pub mod markup;
+mod section;
+mod serialize;
 mod structs;
+pub mod text;
+
+pub use header::{MsbHeader, TextEncoding};
+pub use section::ExtraSection;
+use section::{section_order, OrderedSection};
+
+#[derive(Debug, thiserror::Error)]
+pub enum MsbError {
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("read error: {0}")]
+    Binrw(#[from] binrw::Error),
+    #[error("unknown byte order mark {0:02X?}")]
+    UnknownByteOrderMark([u8; 2]),
+    #[error("unknown text encoding {0}")]
+    UnknownEncoding(u8),
+    #[error("text at {offset:#X} is not valid in the encoding of the file")]
+    InvalidText { offset: u64 },
+    #[error("text {index} can't be written in the encoding of the file")]
+    UnencodableText { index: usize },
+    #[error("unknown flow type {typ} at {offset:#X}")]
+    UnknownFlowType { typ: u8, offset: u64 },
+    #[error("switch at {offset:#X} has an invalid branch offset")]
+    InvalidBranchOffset { offset: u64 },
+    #[error("switch at {offset:#X} has a negative branch count")]
+    InvalidBranchCount { offset: u64 },
+    #[error("label at {offset:#X} is not valid Shift-JIS")]
+    InvalidLabel { offset: u64 },
+    #[error("label {0} is not ASCII")]
+    NonAsciiLabel(String),
+    #[error("label {0} is longer than 255 bytes")]
+    LabelTooLong(String),
+    #[error("{atr_count} attributes for {text_count} texts")]
+    AtrCountMismatch { atr_count: usize, text_count: usize },
+    #[error("text {index} has {found} attribute bytes, expected {expected}")]
+    AtrLengthMismatch {
+        index: usize,
+        expected: usize,
+        found: usize,
+    },
+}
 
 #[derive(Default, Debug)]
 pub struct TextSegment {
@@ -19,19 +70,79 @@ pub struct TextSegment {
     pub text: Vec<u16>,
 }
 
+/// how the labels of a LBL1/FEN1 section were stored, so that they can be written
+/// back the same way
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelLayout {
+    pub bucket_count: u32,
+    /// all labels in the order of the file, labels that aren't in here are written
+    /// after the others in their bucket, sorted by index
+    pub order: Vec<String>,
+}
+
+impl LabelLayout {
+    pub fn new(bucket_count: u32) -> Self {
+        Self {
+            bucket_count,
+            order: Vec::new(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Msbt {
+    pub header: MsbHeader,
     pub lbl: HashMap<String, u32>,
+    pub lbl_layout: LabelLayout,
     pub text: Vec<TextSegment>,
+    /// all sections besides LBL1, ATR1 and TXT2
+    pub extra_sections: Vec<ExtraSection>,
+    /// magic of every section in the order of the original file, used to write them
+    /// in the same order
+    pub section_order: Vec<[u8; 4]>,
 }
 
 #[derive(Debug)]
 pub struct Msbf {
+    pub header: MsbHeader,
     pub flows: Vec<FlowEntry>,
     pub entrypoints: HashMap<String, u32>,
+    pub entrypoint_layout: LabelLayout,
+    /// all sections besides FLW3 and FEN1
+    pub extra_sections: Vec<ExtraSection>,
+    /// magic of every section in the order of the original file, used to write them
+    /// in the same order
+    pub section_order: Vec<[u8; 4]>,
+}
+
+impl Default for Msbt {
+    fn default() -> Self {
+        Self {
+            header: MsbHeader::msbt(),
+            lbl: HashMap::new(),
+            lbl_layout: LabelLayout::new(31),
+            text: Vec::new(),
+            extra_sections: Vec::new(),
+            section_order: Vec::new(),
+        }
+    }
 }
 
-#[derive(Debug, Clone)]
+impl Default for Msbf {
+    fn default() -> Self {
+        Self {
+            header: MsbHeader::msbf(),
+            flows: Vec::new(),
+            entrypoints: HashMap::new(),
+            entrypoint_layout: LabelLayout::new(19),
+            extra_sections: Vec::new(),
+            section_order: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum FlowEntry {
     Start {
         next: i16,
@@ -63,6 +174,16 @@ fn align_next(num: u64, alignment: u64) -> u64 {
         .bitand((alignment - 1).not())
 }
 
+// the length comes from the file, so only what is actually there gets allocated
+fn read_section_data<R: Read>(r: &mut R, len: u32) -> std::io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    r.by_ref().take(len.into()).read_to_end(&mut data)?;
+    if data.len() != len as usize {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(data)
+}
+
 fn entrypoint_hash(bytes: &[u8], entries: usize) -> usize {
     let mut hash: u32 = 0;
     for b in bytes {
@@ -74,37 +195,49 @@ fn entrypoint_hash(bytes: &[u8], entries: usize) -> usize {
 pub fn parse_lbl<R: Read + Seek>(
     r: &mut R,
     cur_seg_start: u64,
-) -> binrw::BinResult<HashMap<String, u32>> {
+    endian: Endian,
+) -> Result<(HashMap<String, u32>, LabelLayout), MsbError> {
     let mut lbl_entries = HashMap::new();
-    let group_count = r.read_be::<u32>()?;
+    let group_count = r.read_type::<u32>(endian)?;
+    let mut layout = LabelLayout::new(group_count);
     for i in 0..group_count {
         r.seek(SeekFrom::Start(cur_seg_start + 4 + i as u64 * 8))?;
-        let count = r.read_be::<u32>()?;
-        let group_offset = r.read_be::<u32>()?;
+        let count = r.read_type::<u32>(endian)?;
+        let group_offset = r.read_type::<u32>(endian)?;
         r.seek(SeekFrom::Start(cur_seg_start + group_offset as u64))?;
         for _ in 0..count {
-            let str_len = r.read_be::<u8>()?;
+            let offset = r.stream_position()?;
+            let str_len = r.read_type::<u8>(endian)?;
             let mut str_buf = vec![0; str_len.into()];
             r.read_exact(&mut str_buf)?;
-            // TODO: this shouldn't panic
-            let str = WINDOWS_31J.decode(&str_buf, DecoderTrap::Strict).unwrap();
-            let value = r.read_be::<u32>()?;
+            let str = WINDOWS_31J
+                .decode(&str_buf, DecoderTrap::Strict)
+                .map_err(|_| MsbError::InvalidLabel { offset })?;
+            let value = r.read_type::<u32>(endian)?;
+            layout.order.push(str.clone());
             lbl_entries.insert(str, value);
         }
     }
-    Ok(lbl_entries)
+    Ok((lbl_entries, layout))
 }
 
-pub fn parse_msbt<R: Read + Seek>(r: &mut R) -> binrw::BinResult<Msbt> {
+pub fn parse_msbt<R: Read + Seek>(r: &mut R) -> Result<Msbt, MsbError> {
+    let header = MsbHeader::read(r)?;
+    let endian = header.endian;
     let file_end = r.seek(SeekFrom::End(0))?;
     let mut next_seg = 0x20;
     let mut lbl_entries = HashMap::new();
+    let mut lbl_layout = LabelLayout::new(31);
     let mut atr1 = Vec::new();
     let mut txt2 = Vec::new();
+    let mut extra_sections = Vec::new();
+    let mut section_order = Vec::new();
     while next_seg < file_end {
         r.seek(SeekFrom::Start(next_seg))?;
+        // the magic is read as big endian to compare it with the byte strings
         let seg_id = r.read_be::<u32>()?;
-        let seg_len = r.read_be::<u32>()?;
+        let seg_len = r.read_type::<u32>(endian)?;
+        section_order.push(seg_id.to_be_bytes());
         r.seek(SeekFrom::Current(8))?; // seek to start of section data
         let cur_seg_start = next_seg + 0x10;
         next_seg += 0x10;
@@ -112,25 +245,25 @@ pub fn parse_msbt<R: Read + Seek>(r: &mut R) -> binrw::BinResult<Msbt> {
         next_seg += (next_seg as isize).neg().rem_euclid(0x10) as u64;
         match seg_id {
             0x4c424c31 /* LBL1 */ => {
-                lbl_entries = parse_lbl(r, cur_seg_start)?;
+                (lbl_entries, lbl_layout) = parse_lbl(r, cur_seg_start, endian)?;
             },
             0x41545231 /* ATR1 */ => {
-                let count = r.read_be::<u32>()?;
-                let dimension = r.read_be::<u32>()?;
+                let count = r.read_type::<u32>(endian)?;
+                let dimension = r.read_type::<u32>(endian)?;
                 for _ in 0..count {
                     let mut current_arr = Vec::new();
                     for _ in 0..dimension {
-                        current_arr.push(r.read_be::<u8>()?);
+                        current_arr.push(r.read_type::<u8>(endian)?);
                     }
                     atr1.push(current_arr);
                 }
             },
             0x54585432 /* TXT2 */ => {
-                let count = r.read_be::<u32>()?;
+                let count = r.read_type::<u32>(endian)?;
                 let mut str_offsets = Vec::new();
                 r.seek(SeekFrom::Start(cur_seg_start + 4))?;
                 for _ in 0..count {
-                    str_offsets.push(r.read_be::<u32>()?);
+                    str_offsets.push(r.read_type::<u32>(endian)?);
                 }
                 str_offsets.push(seg_len);
                 txt2.reserve(str_offsets.len());
@@ -139,42 +272,60 @@ pub fn parse_msbt<R: Read + Seek>(r: &mut R) -> binrw::BinResult<Msbt> {
                 // stop there
                 // TODO use array_windows when stable
                 for window in str_offsets.windows(2) {
-                    let len = (window[1] - window[0]) / 2 - 1;
-                    r.seek(SeekFrom::Start(cur_seg_start + window[0] as u64))?;
-                    let mut utf16buf = Vec::with_capacity(len as usize);
-                    for _ in 0..len {
-                        utf16buf.push(r.read_be::<u16>()?);
-                    }
-                    txt2.push(utf16buf);
+                    let offset = cur_seg_start + window[0] as u64;
+                    // the length in bytes without the null terminator
+                    let len = window[1]
+                        .checked_sub(window[0])
+                        .and_then(|len| len.checked_sub(header.encoding.unit_size()))
+                        .ok_or(MsbError::InvalidText { offset })?;
+                    r.seek(SeekFrom::Start(offset))?;
+                    txt2.push(read_text(r, len as usize, header.encoding, endian, offset)?);
                 }
             },
-            // TODO: proper error
-            _ => panic!("unknown seg: {:X}", seg_id),
+            _ => {
+                let data = read_section_data(r, seg_len)?;
+                extra_sections.push(ExtraSection::parse(seg_id.to_be_bytes(), data, endian));
+            }
         };
     }
     // add atr to txt
-    assert_eq!(atr1.len(), txt2.len());
+    if atr1.len() != txt2.len() {
+        return Err(MsbError::AtrCountMismatch {
+            atr_count: atr1.len(),
+            text_count: txt2.len(),
+        });
+    }
     let text = txt2
         .into_iter()
         .zip(atr1.into_iter())
         .map(|(text, atr)| TextSegment { atr, text })
         .collect();
     Ok(Msbt {
+        header,
         lbl: lbl_entries,
+        lbl_layout,
         text,
+        extra_sections,
+        section_order,
     })
 }
 
-pub fn parse_msbf<R: Read + Seek>(r: &mut R) -> binrw::BinResult<Msbf> {
+pub fn parse_msbf<R: Read + Seek>(r: &mut R) -> Result<Msbf, MsbError> {
+    let header = MsbHeader::read(r)?;
+    let endian = header.endian;
     let file_end = r.seek(SeekFrom::End(0))?;
     let mut next_seg = 0x20;
     let mut entrypoints = HashMap::new();
+    let mut entrypoint_layout = LabelLayout::new(19);
     let mut flows = Vec::new();
-    // let mut txt2 = Vec::new();
+    let mut extra_sections = Vec::new();
+    let mut section_order = Vec::new();
     while next_seg < file_end {
         r.seek(SeekFrom::Start(next_seg))?;
+        // the magic is read as big endian to compare it with the byte strings
         let seg_id = r.read_be::<u32>()?;
-        let seg_len = r.read_be::<u32>()?;
+        let seg_len = r.read_type::<u32>(endian)?;
+        section_order.push(seg_id.to_be_bytes());
         r.seek(SeekFrom::Current(8))?; // seek to start of section data
         let cur_seg_start = next_seg + 0x10;
         next_seg += 0x10;
@@ -182,16 +333,16 @@ pub fn parse_msbf<R: Read + Seek>(r: &mut R) -> binrw::BinResult<Msbf> {
         next_seg = align_next(next_seg, 0x10);
         match seg_id {
             0x46454E31 /* FEN1 */ => {
-                entrypoints = parse_lbl(r, cur_seg_start)?;
+                (entrypoints, entrypoint_layout) = parse_lbl(r, cur_seg_start, endian)?;
             },
             0x464C5733 /* FLW3 */ => {
-                let flow_count = r.read_be::<u16>()?;
-                let _branch_count = r.read_be::<u16>()?;
+                let flow_count = r.read_type::<u16>(endian)?;
+                let _branch_count = r.read_type::<u16>(endian)?;
                 let flow_start = cur_seg_start + 0x10;
                 let branch_start = flow_start + 0x10 * flow_count as u64;
                 for i in 0..flow_count as u64 {
                     r.seek(SeekFrom::Start(flow_start + i * 0x10))?;
-                    let raw_flw = r.read_be::<RawFlw3>()?;
+                    let raw_flw = r.read_type::<RawFlw3>(endian)?;
                     match raw_flw.typ {
                         1 => {
                             flows.push(FlowEntry::Text{
@@ -202,11 +353,21 @@ pub fn parse_msbf<R: Read + Seek>(r: &mut R) -> binrw::BinResult<Msbf> {
                         },
                         2 => {
                             let mut branches = Vec::new();
-                            let branch_offset = raw_flw.param5;
-                            let branch_count = raw_flw.param4;
-                            r.seek(SeekFrom::Start(branch_start + 2 * branch_offset as u64))?;
+                            let branch_count = u16::try_from(raw_flw.param4).map_err(|_| {
+                                MsbError::InvalidBranchCount {
+                                    offset: flow_start + i * 0x10,
+                                }
+                            })?;
+                            let branch_offset = u64::try_from(raw_flw.param5)
+                                .ok()
+                                .and_then(|offset| offset.checked_mul(2))
+                                .and_then(|offset| branch_start.checked_add(offset))
+                                .ok_or(MsbError::InvalidBranchOffset {
+                                    offset: flow_start + i * 0x10,
+                                })?;
+                            r.seek(SeekFrom::Start(branch_offset))?;
                             for _ in 0..branch_count {
-                                branches.push(r.read_be::<i16>()?);
+                                branches.push(r.read_type::<i16>(endian)?);
                             }
                             flows.push(FlowEntry::Switch{
                                 param1: raw_flw.param1,
@@ -228,49 +389,207 @@ pub fn parse_msbf<R: Read + Seek>(r: &mut R) -> binrw::BinResult<Msbf> {
                         4 => {
                             flows.push(FlowEntry::Start{next: raw_flw.next});
                         }
-                        _ => panic!("unknown type: {:X}", raw_flw.typ),
+                        typ => {
+                            return Err(MsbError::UnknownFlowType {
+                                typ,
+                                offset: flow_start + i * 0x10,
+                            })
+                        }
                     }
                 }
             },
-            // TODO: proper error
-            _ => panic!("unknown seg: {:X}", seg_id),
+            _ => {
+                let data = read_section_data(r, seg_len)?;
+                extra_sections.push(ExtraSection::parse(seg_id.to_be_bytes(), data, endian));
+            }
         };
     }
-    Ok(Msbf { entrypoints, flows })
+    Ok(Msbf {
+        header,
+        entrypoints,
+        entrypoint_layout,
+        flows,
+        extra_sections,
+        section_order,
+    })
+}
+
+// all encodings are converted to UTF-16, control sequences are copied as they are
+fn read_text<R: Read + Seek>(
+    r: &mut R,
+    len: usize,
+    encoding: TextEncoding,
+    endian: Endian,
+    offset: u64,
+) -> Result<Vec<u16>, MsbError> {
+    let invalid = || MsbError::InvalidText { offset };
+    let mut buf = vec![0u8; len];
+    r.read_exact(&mut buf)?;
+    let mut c = Cursor::new(buf.as_slice());
+    let mut text = Vec::with_capacity(len);
+    while (c.position() as usize) < len {
+        match encoding {
+            TextEncoding::Utf8 => {
+                let rest = &buf[c.position() as usize..];
+                // 0x0E and 0x0F never appear inside multi byte characters
+                let text_len = rest
+                    .iter()
+                    .position(|&b| u16::from(b) == CONTROL_START || u16::from(b) == CONTROL_END)
+                    .unwrap_or(rest.len());
+                if text_len == 0 {
+                    c.set_position(c.position() + 1);
+                    read_control(&mut c, rest[0].into(), endian, &mut text).ok_or_else(invalid)?;
+                } else {
+                    let s = std::str::from_utf8(&rest[..text_len]).map_err(|_| invalid())?;
+                    text.extend(s.encode_utf16());
+                    c.set_position(c.position() + text_len as u64);
+                }
+            }
+            TextEncoding::Utf16 => text.push(c.read_type::<u16>(endian).map_err(|_| invalid())?),
+            TextEncoding::Utf32 => {
+                let unit = c.read_type::<u32>(endian).map_err(|_| invalid())?;
+                match char::from_u32(unit).ok_or_else(invalid)? {
+                    '\u{E}' | '\u{F}' => {
+                        read_control(&mut c, unit as u16, endian, &mut text).ok_or_else(invalid)?
+                    }
+                    ch => text.extend(ch.encode_utf16(&mut [0; 2]).iter()),
+                }
+            }
+        }
+    }
+    Ok(text)
+}
+
+// copies the rest of the control sequence as u16, after the code unit that starts it
+fn read_control(
+    c: &mut Cursor<&[u8]>,
+    start: u16,
+    endian: Endian,
+    text: &mut Vec<u16>,
+) -> Option<()> {
+    text.push(start);
+    let group = c.read_type::<u16>(endian).ok()?;
+    let typ = c.read_type::<u16>(endian).ok()?;
+    text.extend([group, typ]);
+    if start == CONTROL_START {
+        let size = c.read_type::<u16>(endian).ok()?;
+        // parameters are stored as whole u16 in the UTF-16 representation
+        if size % 2 != 0 {
+            return None;
+        }
+        text.push(size);
+        for _ in 0..size / 2 {
+            text.push(c.read_type::<u16>(endian).ok()?);
+        }
+    }
+    Some(())
+}
+
+// writes the text including the null terminator
+fn write_text<WS: Write + Seek>(
+    ws: &mut WS,
+    text: &[u16],
+    encoding: TextEncoding,
+    endian: Endian,
+    index: usize,
+) -> Result<(), MsbError> {
+    let unencodable = || MsbError::UnencodableText { index };
+    if encoding == TextEncoding::Utf16 {
+        ws.write_type(&text, endian)?;
+        ws.write_type(&0u16, endian)?;
+        return Ok(());
+    }
+    for part in text::parse_text(text) {
+        match part {
+            TextPart::Text(text) => {
+                // leftovers of control sequences can't be read back
+                if text.contains(['\u{E}', '\u{F}']) {
+                    return Err(unencodable());
+                }
+                if encoding == TextEncoding::Utf8 {
+                    ws.write_all(text.as_bytes())?;
+                } else {
+                    for c in text.chars() {
+                        ws.write_type(&(c as u32), endian)?;
+                    }
+                }
+            }
+            TextPart::Control(tag) => {
+                // the code unit that starts the sequence in the encoding, the rest as u16
+                let units = text::write_text(&[TextPart::Control(tag)]);
+                if encoding == TextEncoding::Utf8 {
+                    ws.write_all(&[units[0] as u8])?;
+                } else {
+                    ws.write_type(&u32::from(units[0]), endian)?;
+                }
+                ws.write_type(&&units[1..], endian)?;
+            }
+            TextPart::Raw(_) => return Err(unencodable()),
+        }
+    }
+    match encoding {
+        TextEncoding::Utf8 => ws.write_all(&[0])?,
+        _ => ws.write_type(&0u32, endian)?,
+    }
+    Ok(())
 }
 
 // stream position is at the end after it finished
 fn write_lbl_fen<WS: Write + Seek>(
     map: &HashMap<String, u32>,
+    layout: &LabelLayout,
     section_name: u32,
-    bucket_count: usize,
+    endian: Endian,
     ws: &mut WS,
-) -> binrw::BinResult<()> {
+) -> Result<(), MsbError> {
     let section_start = ws.stream_position()?;
     // FEN1/LBL1
     // we don't know the total section size yet
-    // but we know there will be 30 groups here
+    // but we know how many groups there will be
     ws.seek(SeekFrom::Start(section_start))?;
     ws.write_be(&section_name)?;
     // length will be filled in later
-    ws.write_be(&0u32)?;
+    ws.write_type(&0u32, endian)?;
     ws.write_all(&[0u8; 8])?;
 
-    let mut buckets: Vec<Vec<(&[u8], u32)>> = vec![vec![]; bucket_count];
-    ws.write_be(&(bucket_count as u32))?;
+    // labels need at least one bucket
+    let bucket_count = if map.is_empty() {
+        layout.bucket_count
+    } else {
+        layout.bucket_count.max(1)
+    } as usize;
+    let positions: HashMap<&str, usize> = layout
+        .order
+        .iter()
+        .enumerate()
+        .map(|(i, label)| (label.as_str(), i))
+        .collect();
+    let mut buckets: Vec<Vec<(&[u8], u32, usize)>> = vec![vec![]; bucket_count];
+    ws.write_type(&(bucket_count as u32), endian)?;
     // sort entries to specific lists
     // it's fine to iterate the hash map here, it will be sorted later
     for (entrypoint, idx) in map {
         // idk if this actually has to be the case, try for now
-        assert!(entrypoint.is_ascii());
+        if !entrypoint.is_ascii() {
+            return Err(MsbError::NonAsciiLabel(entrypoint.clone()));
+        }
+        if entrypoint.len() > u8::MAX as usize {
+            return Err(MsbError::LabelTooLong(entrypoint.clone()));
+        }
         let encoded_entrypoint = entrypoint.as_bytes();
         let bucket = entrypoint_hash(encoded_entrypoint, bucket_count);
-        buckets[bucket].push((encoded_entrypoint, *idx));
+        // labels that weren't read are sorted after the others
+        let position = positions
+            .get(entrypoint.as_str())
+            .copied()
+            .unwrap_or(usize::MAX);
+        buckets[bucket].push((encoded_entrypoint, *idx, position));
     }
     for bucket in &mut buckets {
-        // sort by referenced index, *in theory* they might be not unique,
-        // but in that case the order doesn't matter
-        bucket.sort_unstable_by_key(|e| e.1);
+        // keep the original order, new labels come after that, sorted by referenced
+        // index, *in theory* they might be not unique, but in that case the order
+        // doesn't matter
+        bucket.sort_unstable_by_key(|e| (e.2, e.1));
     }
 
     let section_data_start = section_start + 16 /* header */;
@@ -282,26 +601,26 @@ fn write_lbl_fen<WS: Write + Seek>(
         let bucket_header_offset = section_data_start + i as u64 * 8 + 4 /* bucket count */;
         // write data
         ws.seek(SeekFrom::Start(section_data_start + seg_data_offset as u64))?;
-        for (lbl, idx) in bucket {
+        for (lbl, idx, _) in bucket {
             // first string len
             ws.write_all(&[lbl.len() as u8])?;
             // then string (no explicit 0 byte at the end)
             ws.write_all(lbl)?;
             // then index (unaligned)
-            ws.write_be(idx)?;
+            ws.write_type(idx, endian)?;
         }
         let new_seg_data_offset = (ws.stream_position()? - section_data_start) as u32;
         ws.seek(SeekFrom::Start(bucket_header_offset))?;
         // placeholder for length
-        ws.write_be(&(bucket.len() as u32))?;
+        ws.write_type(&(bucket.len() as u32), endian)?;
         // actual data offset
-        ws.write_be(&seg_data_offset)?;
+        ws.write_type(&seg_data_offset, endian)?;
         seg_data_offset = new_seg_data_offset;
     }
 
     // write FEN1 length
     ws.seek(SeekFrom::Start(section_start + 4))?;
-    ws.write_be(&seg_data_offset)?;
+    ws.write_type(&seg_data_offset, endian)?;
 
     // section_start is aligned, so we can only use seg_data_offset to determine the padding
     let pads = (seg_data_offset as isize).neg().rem_euclid(16);
@@ -315,13 +634,38 @@ fn write_lbl_fen<WS: Write + Seek>(
 }
 
 impl Msbf {
-    pub fn write_msbf<WS: Write + Seek>(&self, ws: &mut WS) -> binrw::BinResult<()> {
-        const HEADER: &[u8; 16] = b"MsgFlwBn\xFE\xFF\0\0\0\x03\0\x02";
-        const EMPTY_HEADER: &[u8; 16] = &[0u8; 16];
-        ws.write_all(HEADER)?;
-        ws.write_all(EMPTY_HEADER)?;
-        // write FLW3
-        let flw_start = 32;
+    pub fn write_msbf<WS: Write + Seek>(&self, ws: &mut WS) -> Result<(), MsbError> {
+        let endian = self.header.endian;
+        let sections = section_order(
+            &self.section_order,
+            &[*b"FLW3", *b"FEN1"],
+            &self.extra_sections,
+        );
+        self.header.write(ws, b"MsgFlwBn", sections.len() as u16)?;
+        for section in sections {
+            match section {
+                OrderedSection::Main(magic) if &magic == b"FLW3" => self.write_flw3(ws)?,
+                OrderedSection::Main(magic) => write_lbl_fen(
+                    &self.entrypoints,
+                    &self.entrypoint_layout,
+                    u32::from_be_bytes(magic),
+                    endian,
+                    ws,
+                )?,
+                OrderedSection::Extra(i) => self.extra_sections[i].write(ws, endian)?,
+            }
+        }
+        // write file length
+        let pos = ws.stream_position()?;
+        ws.seek(SeekFrom::Start(0x12))?;
+        ws.write_type(&(pos as u32), endian)?;
+        Ok(())
+    }
+
+    // stream position is at the end after it finished
+    fn write_flw3<WS: Write + Seek>(&self, ws: &mut WS) -> Result<(), MsbError> {
+        let endian = self.header.endian;
+        let flw_start = ws.stream_position()?;
         // section header of 16
         ws.seek(SeekFrom::Current(16))?;
         // decompose flows into raw components
@@ -388,14 +732,14 @@ impl Msbf {
             };
             flows.push(raw_flow);
         }
-        ws.write_be(&(flows.len() as u16))?;
-        ws.write_be(&(branch_points.len() as u16))?;
+        ws.write_type(&(flows.len() as u16), endian)?;
+        ws.write_type(&(branch_points.len() as u16), endian)?;
         ws.write_all(&[0u8; 12])?;
         for flow in &flows {
-            ws.write_be(flow)?;
+            ws.write_type(flow, endian)?;
         }
         for branch_point in &branch_points {
-            ws.write_be(branch_point)?;
+            ws.write_type(branch_point, endian)?;
         }
         // pad to 16 bytes
         let flw_end = ws.stream_position()?;
@@ -403,47 +747,71 @@ impl Msbf {
         for _ in 0..pads {
             ws.write_all(&[0xAB])?;
         }
-        let fen1_start = flw_end + pads as u64;
+        let flw3_end = flw_end + pads as u64;
         // write FLW3 header
         ws.seek(SeekFrom::Start(flw_start))?;
         ws.write_all(b"FLW3")?;
-        ws.write_be(&(flw_end as u32 - flw_start as u32 - 16/* header */))?;
+        ws.write_type(
+            &(flw_end as u32 - flw_start as u32 - 16/* header */),
+            endian,
+        )?;
         ws.write_all(&[0u8; 8])?;
-        // FEN1
-        ws.seek(SeekFrom::Start(fen1_start))?;
-        write_lbl_fen(&self.entrypoints, u32::from_be_bytes(*b"FEN1"), 19, ws)?;
+        ws.seek(SeekFrom::Start(flw3_end))?;
+        Ok(())
+    }
+}
+
+impl Msbt {
+    pub fn write_msbt<WS: Write + Seek>(&self, ws: &mut WS) -> Result<(), MsbError> {
+        let endian = self.header.endian;
+        let sections = section_order(
+            &self.section_order,
+            &[*b"LBL1", *b"ATR1", *b"TXT2"],
+            &self.extra_sections,
+        );
+        self.header.write(ws, b"MsgStdBn", sections.len() as u16)?;
+        for section in sections {
+            match section {
+                OrderedSection::Main(magic) if &magic == b"ATR1" => self.write_atr1(ws)?,
+                OrderedSection::Main(magic) if &magic == b"TXT2" => self.write_txt2(ws)?,
+                OrderedSection::Main(magic) => write_lbl_fen(
+                    &self.lbl,
+                    &self.lbl_layout,
+                    u32::from_be_bytes(magic),
+                    endian,
+                    ws,
+                )?,
+                OrderedSection::Extra(i) => self.extra_sections[i].write(ws, endian)?,
+            }
+        }
         // write file length
         let pos = ws.stream_position()?;
         ws.seek(SeekFrom::Start(0x12))?;
-        ws.write_be(&(pos as u32))?;
+        ws.write_type(&(pos as u32), endian)?;
         Ok(())
     }
-}
 
-impl Msbt {
-    pub fn write_msbt<WS: Write + Seek>(&self, ws: &mut WS) -> binrw::BinResult<()> {
-        const HEADER: &[u8; 16] = b"MsgStdBn\xFE\xFF\0\0\x01\x03\0\x03";
-        const EMPTY_HEADER: &[u8; 16] = &[0u8; 16];
-        ws.write_all(HEADER)?;
-        ws.write_all(EMPTY_HEADER)?;
-        // LBL1
-        write_lbl_fen(&self.lbl, u32::from_be_bytes(*b"LBL1"), 31, ws)?;
-        // ATR1
+    // stream position is at the end after it finished
+    fn write_atr1<WS: Write + Seek>(&self, ws: &mut WS) -> Result<(), MsbError> {
+        let endian = self.header.endian;
         let atr1_start = ws.stream_position()?;
         let atr1_data_start = atr1_start + 16;
         // verify that all have the same atr lengths
         // TODO: maybe just pad to longest?
-        let atr_len = self.text[0].atr.len();
-        for text in &self.text {
+        let atr_len = self.text.first().map_or(0, |text| text.atr.len());
+        for (index, text) in self.text.iter().enumerate() {
             if atr_len != text.atr.len() {
-                // TODO error, no panic
-                panic!("missmatched atr lengths");
+                return Err(MsbError::AtrLengthMismatch {
+                    index,
+                    expected: atr_len,
+                    found: text.atr.len(),
+                });
             }
         }
         ws.seek(SeekFrom::Start(atr1_data_start))?;
         // dimensions
-        ws.write_be(&(self.text.len() as u32))?;
-        ws.write_be(&(atr_len as u32))?;
+        ws.write_type(&(self.text.len() as u32), endian)?;
+        ws.write_type(&(atr_len as u32), endian)?;
         // data
         for text in &self.text {
             for atr in &text.atr {
@@ -460,22 +828,26 @@ impl Msbt {
         // write ATR1 header
         ws.seek(SeekFrom::Start(atr1_start))?;
         ws.write_all(b"ATR1")?;
-        ws.write_be(&(atr1_end as u32 - atr1_start as u32 - 16))?;
+        ws.write_type(&(atr1_end as u32 - atr1_start as u32 - 16), endian)?;
+        ws.seek(SeekFrom::Start(atr1_end + pads as u64))?;
+        Ok(())
+    }
 
-        // TXT2
-        let txt2_start = atr1_end + pads as u64;
+    // stream position is at the end after it finished
+    fn write_txt2<WS: Write + Seek>(&self, ws: &mut WS) -> Result<(), MsbError> {
+        let endian = self.header.endian;
+        let txt2_start = ws.stream_position()?;
         let txt2_data_start = txt2_start + 16;
 
         ws.seek(SeekFrom::Start(txt2_data_start))?;
         // write total count
-        ws.write_be(&(self.text.len() as u32))?;
+        ws.write_type(&(self.text.len() as u32), endian)?;
         let mut txt2_offset: u32 = 4 + self.text.len() as u32 * 4;
         for (i, txt) in self.text.iter().enumerate() {
             ws.seek(SeekFrom::Start(txt2_data_start + 4 + i as u64 * 4))?;
-            ws.write_be(&txt2_offset)?;
+            ws.write_type(&txt2_offset, endian)?;
             ws.seek(SeekFrom::Start(txt2_data_start + txt2_offset as u64))?;
-            ws.write_be(&txt.text)?;
-            ws.write_be(&0u16)?;
+            write_text(ws, &txt.text, self.header.encoding, endian, i)?;
             txt2_offset = (ws.stream_position()? - txt2_data_start) as u32;
         }
 
@@ -492,11 +864,8 @@ impl Msbt {
         ws.seek(SeekFrom::Start(txt2_start))?;
         ws.write_all(b"TXT2")?;
         // section length
-        ws.write_be(&(txt2_end as u32 - txt2_data_start as u32))?;
-
-        // write file length
-        ws.seek(SeekFrom::Start(0x12))?;
-        ws.write_be(&(pos as u32))?;
+        ws.write_type(&(txt2_end as u32 - txt2_data_start as u32), endian)?;
+        ws.seek(SeekFrom::Start(pos))?;
         Ok(())
     }
 }
@@ -511,3 +880,273 @@ impl FlowEntry {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, fs, io::Cursor, path::Path};
+
+    use binrw::Endian;
+
+    use crate::{
+        markup, parse_msbf, parse_msbt, text, ExtraSection, FlowEntry, LabelLayout, MsbError, Msbf,
+        Msbt, TextEncoding, TextSegment,
+    };
+
+    fn test_msbt() -> Msbt {
+        Msbt {
+            lbl: HashMap::from([("0".to_string(), 0)]),
+            text: vec![TextSegment {
+                atr: vec![0, 0],
+                text: "hello".encode_utf16().collect(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    pub fn test_errors() {
+        let mut msbt = test_msbt();
+        let mut buf = Vec::new();
+        msbt.write_msbt(&mut Cursor::new(&mut buf)).unwrap();
+        // cut off in the middle of TXT2
+        buf.truncate(buf.len() - 0x10);
+        assert!(parse_msbt(&mut Cursor::new(&buf)).is_err());
+
+        // text offset after the end of TXT2
+        let mut buf = Vec::new();
+        msbt.write_msbt(&mut Cursor::new(&mut buf)).unwrap();
+        let txt2 = buf.windows(4).position(|w| w == b"TXT2").unwrap();
+        buf[txt2 + 0x14..txt2 + 0x18].copy_from_slice(&[0x7F; 4]);
+        assert!(matches!(
+            parse_msbt(&mut Cursor::new(&buf)),
+            Err(MsbError::InvalidText { .. })
+        ));
+
+        // negative branch offset
+        let msbf = Msbf {
+            flows: vec![FlowEntry::Switch {
+                subtype: 0,
+                param1: 0,
+                param2: 0,
+                param3: 0,
+                branches: vec![-1, -1],
+            }],
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        msbf.write_msbf(&mut Cursor::new(&mut buf)).unwrap();
+        let flw3 = buf.windows(4).position(|w| w == b"FLW3").unwrap();
+        // param5 of the first flow
+        buf[flw3 + 0x2E..flw3 + 0x30].copy_from_slice(&[0xFF; 2]);
+        assert!(matches!(
+            parse_msbf(&mut Cursor::new(&buf)),
+            Err(MsbError::InvalidBranchOffset { .. })
+        ));
+
+        // negative branch count
+        let mut buf = Vec::new();
+        msbf.write_msbf(&mut Cursor::new(&mut buf)).unwrap();
+        // param4 of the first flow
+        buf[flw3 + 0x2C..flw3 + 0x2E].copy_from_slice(&[0xFF; 2]);
+        assert!(matches!(
+            parse_msbf(&mut Cursor::new(&buf)),
+            Err(MsbError::InvalidBranchCount { .. })
+        ));
+
+        // unknown section longer than the file
+        let mut unknown = test_msbt();
+        unknown.extra_sections = vec![ExtraSection::Unknown {
+            magic: *b"XYZ1",
+            data: vec![1, 2, 3],
+        }];
+        unknown.section_order = vec![*b"LBL1", *b"ATR1", *b"TXT2", *b"XYZ1"];
+        let mut buf = Vec::new();
+        unknown.write_msbt(&mut Cursor::new(&mut buf)).unwrap();
+        let xyz1 = buf.windows(4).position(|w| w == b"XYZ1").unwrap();
+        buf[xyz1 + 4..xyz1 + 8].copy_from_slice(&[0x7F, 0xFF, 0xFF, 0xFF]);
+        assert!(matches!(
+            parse_msbt(&mut Cursor::new(&buf)),
+            Err(MsbError::Io(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof
+        ));
+
+        msbt.text.push(TextSegment::default());
+        assert!(matches!(
+            msbt.write_msbt(&mut Cursor::new(Vec::new())),
+            Err(MsbError::AtrLengthMismatch { index: 1, .. })
+        ));
+
+        let mut msbt = test_msbt();
+        msbt.lbl.insert("ラベル".to_string(), 0);
+        assert!(matches!(
+            msbt.write_msbt(&mut Cursor::new(Vec::new())),
+            Err(MsbError::NonAsciiLabel(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_extra_sections() {
+        let mut msbt = test_msbt();
+        msbt.extra_sections = vec![
+            ExtraSection::Nli1(vec![(1, 0)]),
+            ExtraSection::Unknown {
+                magic: *b"XYZ1",
+                data: vec![1, 2, 3],
+            },
+            ExtraSection::Tsy1(vec![7]),
+        ];
+        msbt.section_order = vec![*b"LBL1", *b"NLI1", *b"ATR1", *b"XYZ1", *b"TXT2"];
+        let mut buf = Vec::new();
+        msbt.write_msbt(&mut Cursor::new(&mut buf)).unwrap();
+        // section count
+        assert_eq!(buf[0x0F], 6);
+
+        let reparsed = parse_msbt(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(reparsed.extra_sections, msbt.extra_sections);
+        // sections without a position are written at the end
+        assert_eq!(
+            reparsed.section_order,
+            vec![*b"LBL1", *b"NLI1", *b"ATR1", *b"XYZ1", *b"TXT2", *b"TSY1"]
+        );
+        assert_eq!(reparsed.text[0].text, msbt.text[0].text);
+        let mut rewritten = Vec::new();
+        reparsed
+            .write_msbt(&mut Cursor::new(&mut rewritten))
+            .unwrap();
+        assert_eq!(rewritten, buf);
+    }
+
+    #[test]
+    pub fn test_header() {
+        for encoding in [TextEncoding::Utf8, TextEncoding::Utf16, TextEncoding::Utf32] {
+            let mut msbt = test_msbt();
+            msbt.header.endian = Endian::Little;
+            msbt.header.encoding = encoding;
+            msbt.header.version = 2;
+            msbt.header.reserved = [1, 2];
+            msbt.text[0].text = "héllo 🗡".encode_utf16().collect();
+            // an icon and the end of a color
+            msbt.text[0].text.extend([0x0E, 3, 1, 2, 1, 0x0F, 0, 3]);
+            msbt.extra_sections = vec![ExtraSection::Tsy1(vec![7])];
+            let mut buf = Vec::new();
+            msbt.write_msbt(&mut Cursor::new(&mut buf)).unwrap();
+            if encoding == TextEncoding::Utf8 {
+                // only the first code unit is in the encoding, the rest are u16
+                let icon = [0x0E, 3, 0, 1, 0, 2, 0, 1, 0, 0x0F, 0, 0, 3, 0, 0];
+                assert!(buf.windows(icon.len()).any(|w| w == icon));
+            }
+            assert_eq!(&buf[8..10], &[0xFF, 0xFE]);
+            assert_eq!(&buf[0x0A..0x0C], &[1, 2]);
+            assert_eq!(buf[0x0C], encoding.to_byte());
+            // little endian section count
+            assert_eq!(&buf[0x0E..0x10], &[4, 0]);
+
+            let reparsed = parse_msbt(&mut Cursor::new(&buf)).unwrap();
+            assert_eq!(reparsed.header.endian, Endian::Little);
+            assert_eq!(reparsed.header.encoding, encoding);
+            assert_eq!(reparsed.header.version, 2);
+            assert_eq!(reparsed.header.reserved, [1, 2]);
+            assert_eq!(reparsed.header.section_count, 4);
+            assert_eq!(reparsed.lbl, msbt.lbl);
+            assert_eq!(reparsed.text[0].text, msbt.text[0].text);
+            assert_eq!(reparsed.extra_sections, msbt.extra_sections);
+        }
+
+        // unpaired surrogates only exist in UTF-16
+        let mut msbt = test_msbt();
+        msbt.header.encoding = TextEncoding::Utf8;
+        msbt.text[0].text = vec![0xD800];
+        assert!(matches!(
+            msbt.write_msbt(&mut Cursor::new(Vec::new())),
+            Err(MsbError::UnencodableText { index: 0 })
+        ));
+        // as are truncated control sequences
+        msbt.text[0].text = vec![0x0E, 3];
+        assert!(matches!(
+            msbt.write_msbt(&mut Cursor::new(Vec::new())),
+            Err(MsbError::UnencodableText { index: 0 })
+        ));
+    }
+
+    #[test]
+    pub fn test_label_layout() {
+        // "k" and "u" end up in the same bucket with 10 buckets, "b" in the next one
+        let msbf = Msbf {
+            flows: vec![FlowEntry::Start { next: -1 }; 3],
+            entrypoints: HashMap::from([
+                ("b".to_string(), 0),
+                ("k".to_string(), 1),
+                ("u".to_string(), 2),
+            ]),
+            entrypoint_layout: LabelLayout {
+                bucket_count: 10,
+                // not sorted by index
+                order: vec!["u".to_string(), "k".to_string()],
+            },
+            ..Default::default()
+        };
+        let mut buf = Vec::new();
+        msbf.write_msbf(&mut Cursor::new(&mut buf)).unwrap();
+        let reparsed = parse_msbf(&mut Cursor::new(&buf)).unwrap();
+        assert_eq!(reparsed.entrypoints, msbf.entrypoints);
+        assert_eq!(reparsed.entrypoint_layout.bucket_count, 10);
+        assert_eq!(reparsed.entrypoint_layout.order, vec!["u", "k", "b"]);
+
+        let mut rewritten = Vec::new();
+        reparsed
+            .write_msbf(&mut Cursor::new(&mut rewritten))
+            .unwrap();
+        assert_eq!(rewritten, buf);
+    }
+
+    fn collect_files(dir: &Path, files: &mut Vec<std::path::PathBuf>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                collect_files(&path, files);
+            } else {
+                files.push(path);
+            }
+        }
+    }
+
+    /// needs a directory with extracted msbf and msbt files in MSB_EVENT_DIR
+    #[test]
+    #[ignore]
+    pub fn test_vanilla_roundtrip() {
+        let dir = std::env::var("MSB_EVENT_DIR").expect("MSB_EVENT_DIR not set");
+        let mut files = Vec::new();
+        collect_files(Path::new(&dir), &mut files);
+        let mut checked = 0;
+        for path in files {
+            let data = fs::read(&path).unwrap();
+            let mut rewritten = Vec::new();
+            match path.extension().and_then(|e| e.to_str()) {
+                Some("msbf") => parse_msbf(&mut Cursor::new(&data))
+                    .unwrap()
+                    .write_msbf(&mut Cursor::new(&mut rewritten))
+                    .unwrap(),
+                Some("msbt") => {
+                    let msbt = parse_msbt(&mut Cursor::new(&data)).unwrap();
+                    // every text has to survive being split into parts and markup
+                    for (index, text) in msbt.text.iter().enumerate() {
+                        let parts = text::parse_text(&text.text);
+                        assert!(
+                            text::write_text(&parts) == text.text,
+                            "{path:?} text {index} changed by parse_text"
+                        );
+                        let markup = markup::to_markup(&parts);
+                        assert!(
+                            text::write_text(&markup::parse_markup(&markup).unwrap()) == text.text,
+                            "{path:?} text {index} changed by the markup {markup:?}"
+                        );
+                    }
+                    msbt.write_msbt(&mut Cursor::new(&mut rewritten)).unwrap()
+                }
+                _ => continue,
+            }
+            assert!(rewritten == data, "{path:?} changed");
+            checked += 1;
+        }
+        assert!(checked > 0, "no msbf or msbt files in {dir}");
+    }
+}
@@ -0,0 +1,294 @@
+//! human readable markup for TXT2 strings
+//!
+//! control sequences are written as tags, everything else is plain text:
+//! - colors: `<red>Goddess Sword</red>`, colors without a name are `<color=5>..</color>`
+//! - tags without parameters: `<choice2>`
+//! - tags with one value: `<icon=A>`, `<pause=30>`
+//! - any other control sequence: `<ctrl=1,2,00001234>` (group, type, parameter bytes as hex)
+//!   and `</ctrl=1,2>` for closing sequences
+//! - code units that aren't valid text: `<raw=D800,000E>`
+//!
+//! `<` and `\` in text have to be escaped as `\<` and `\\`
+
+use std::fmt::Write;
+
+use crate::{
+    text::{parse_text, write_text, ControlTag, TextPart, COLOR_RESET},
+    TextSegment,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum MarkupError {
+    #[error("tag starting at {0} is never closed")]
+    UnclosedTag(usize),
+    #[error("unknown tag <{0}>")]
+    UnknownTag(String),
+    #[error("invalid value in tag <{0}>")]
+    InvalidTag(String),
+    #[error("escape at the end of the text")]
+    TrailingEscape,
+}
+
+/// color names, the value is the index in the color table
+const COLORS: &[(&str, u16)] = &[
+    ("red", 0),
+    ("green", 1),
+    ("blue", 2),
+    ("yellow", 3),
+    ("orange", 4),
+    ("purple", 5),
+];
+
+/// names for the values of the icon tag
+const ICONS: &[(&str, u16)] = &[
+    ("A", 0),
+    ("B", 1),
+    ("1", 2),
+    ("2", 3),
+    ("C", 4),
+    ("Z", 5),
+    ("PLUS", 6),
+    ("MINUS", 7),
+    ("HOME", 8),
+    ("DPAD", 9),
+];
+
+/// converts the parts to markup, [`parse_markup`] turns it back into the same parts
+pub fn to_markup(parts: &[TextPart]) -> String {
+    let mut out = String::new();
+    // name of the currently open color, so that the reset closes it
+    let mut open_color: Option<&str> = None;
+    for part in parts {
+        match part {
+            TextPart::Text(text) => {
+                for c in text.chars() {
+                    if c == '<' || c == '\\' {
+                        out.push('\\');
+                    }
+                    out.push(c);
+                }
+            }
+            TextPart::Control(ControlTag::Color(COLOR_RESET)) => {
+                let _ = write!(out, "</{}>", open_color.take().unwrap_or("color"));
+            }
+            TextPart::Control(ControlTag::Color(color)) => {
+                match COLORS.iter().find(|(_, c)| c == color) {
+                    Some((name, _)) => {
+                        let _ = write!(out, "<{name}>");
+                        open_color = Some(name);
+                    }
+                    None => {
+                        let _ = write!(out, "<color={color}>");
+                        open_color = None;
+                    }
+                }
+            }
+            TextPart::Control(ControlTag::Pause(frames)) => {
+                let _ = write!(out, "<pause={frames}>");
+            }
+            TextPart::Control(ControlTag::Icon(icon)) => {
+                match ICONS.iter().find(|(_, i)| i == icon) {
+                    Some((name, _)) => {
+                        let _ = write!(out, "<icon={name}>");
+                    }
+                    None => {
+                        let _ = write!(out, "<icon={icon}>");
+                    }
+                }
+            }
+            TextPart::Control(ControlTag::Choice(count)) => {
+                let _ = write!(out, "<choice{count}>");
+            }
+            TextPart::Control(ControlTag::Other { group, typ, params }) => {
+                let _ = write!(out, "<ctrl={group},{typ}");
+                if !params.is_empty() {
+                    out.push(',');
+                    for b in params {
+                        let _ = write!(out, "{b:02X}");
+                    }
+                }
+                out.push('>');
+            }
+            TextPart::Control(ControlTag::End { group, typ }) => {
+                let _ = write!(out, "</ctrl={group},{typ}>");
+            }
+            TextPart::Raw(units) => {
+                let units: Vec<String> = units.iter().map(|u| format!("{u:04X}")).collect();
+                let _ = write!(out, "<raw={}>", units.join(","));
+            }
+        }
+    }
+    out
+}
+
+/// parses the markup into text and control sequences
+pub fn parse_markup(markup: &str) -> Result<Vec<TextPart>, MarkupError> {
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut chars = markup.char_indices();
+    while let Some((pos, c)) = chars.next() {
+        match c {
+            '\\' => text.push(chars.next().ok_or(MarkupError::TrailingEscape)?.1),
+            '<' => {
+                let rest = &markup[pos + 1..];
+                let len = rest.find('>').ok_or(MarkupError::UnclosedTag(pos))?;
+                let tag = &rest[..len];
+                // skip the tag and the closing '>'
+                chars.nth(tag.chars().count());
+                if !text.is_empty() {
+                    parts.push(TextPart::Text(std::mem::take(&mut text)));
+                }
+                parts.push(parse_tag(tag)?);
+            }
+            _ => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(TextPart::Text(text));
+    }
+    Ok(parts)
+}
+
+fn parse_tag(tag: &str) -> Result<TextPart, MarkupError> {
+    let invalid = || MarkupError::InvalidTag(tag.to_string());
+    let parse_num = |s: &str| s.parse::<u16>().map_err(|_| invalid());
+
+    if let Some(name) = tag.strip_prefix('/') {
+        if let Some(args) = name.strip_prefix("ctrl=") {
+            let (group, typ) = args.split_once(',').ok_or_else(invalid)?;
+            return Ok(TextPart::Control(ControlTag::End {
+                group: parse_num(group)?,
+                typ: parse_num(typ)?,
+            }));
+        }
+        if name == "color" || COLORS.iter().any(|(c, _)| *c == name) {
+            return Ok(TextPart::Control(ControlTag::Color(COLOR_RESET)));
+        }
+        return Err(MarkupError::UnknownTag(tag.to_string()));
+    }
+
+    let (name, value) = match tag.split_once('=') {
+        Some((name, value)) => (name, Some(value)),
+        None => (tag, None),
+    };
+    let tag_part = match (name, value) {
+        ("color", Some(color)) => TextPart::Control(ControlTag::Color(parse_num(color)?)),
+        ("ctrl", Some(args)) => {
+            let mut args = args.split(',');
+            let group = parse_num(args.next().ok_or_else(invalid)?)?;
+            let typ = parse_num(args.next().ok_or_else(invalid)?)?;
+            let params = match args.next() {
+                Some(hex) => parse_hex_bytes(hex).ok_or_else(invalid)?,
+                None => Vec::new(),
+            };
+            if args.next().is_some() {
+                return Err(invalid());
+            }
+            TextPart::Control(ControlTag::Other { group, typ, params })
+        }
+        ("raw", Some(units)) => TextPart::Raw(
+            units
+                .split(',')
+                .map(|u| u16::from_str_radix(u, 16).map_err(|_| invalid()))
+                .collect::<Result<_, _>>()?,
+        ),
+        ("pause", Some(frames)) => TextPart::Control(ControlTag::Pause(parse_num(frames)?)),
+        ("icon", Some(icon)) => {
+            let icon = match ICONS.iter().find(|(name, _)| *name == icon) {
+                Some((_, icon)) => *icon,
+                None => parse_num(icon)?,
+            };
+            TextPart::Control(ControlTag::Icon(icon))
+        }
+        ("choice2", None) => TextPart::Control(ControlTag::Choice(2)),
+        ("choice3", None) => TextPart::Control(ControlTag::Choice(3)),
+        ("choice4", None) => TextPart::Control(ControlTag::Choice(4)),
+        (name, None) => match COLORS.iter().find(|(c, _)| *c == name) {
+            Some((_, color)) => TextPart::Control(ControlTag::Color(*color)),
+            None => return Err(MarkupError::UnknownTag(tag.to_string())),
+        },
+        _ => return Err(MarkupError::UnknownTag(tag.to_string())),
+    };
+    Ok(tag_part)
+}
+
+fn parse_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    // an odd length leaves half a byte at the end, which fails to parse
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl TextSegment {
+    pub fn markup(&self) -> String {
+        to_markup(&parse_text(&self.text))
+    }
+
+    pub fn set_markup(&mut self, markup: &str) -> Result<(), MarkupError> {
+        self.text = write_text(&parse_markup(markup)?);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_markup, to_markup};
+    use crate::text::{parse_text, write_text, ControlTag, TextPart};
+
+    #[test]
+    pub fn test_markup() {
+        let parts = parse_markup("You got the <red>Goddess Sword</red>!<choice2>").unwrap();
+        assert_eq!(
+            parts,
+            vec![
+                TextPart::Text("You got the ".into()),
+                TextPart::Control(ControlTag::Color(0)),
+                TextPart::Text("Goddess Sword".into()),
+                TextPart::Control(ControlTag::Color(0xFFFF)),
+                TextPart::Text("!".into()),
+                TextPart::Control(ControlTag::Choice(2)),
+            ]
+        );
+        assert_eq!(
+            parse_markup("<icon=A><icon=42><pause=30>").unwrap(),
+            vec![
+                TextPart::Control(ControlTag::Icon(0)),
+                TextPart::Control(ControlTag::Icon(42)),
+                TextPart::Control(ControlTag::Pause(30)),
+            ]
+        );
+        // the generic tag results in the same control sequence
+        assert_eq!(
+            write_text(&parse_markup("<icon=42>").unwrap()),
+            write_text(&parse_markup("<ctrl=3,1,002A>").unwrap())
+        );
+        assert!(parse_markup("<red").is_err());
+        assert!(parse_markup("<unknown>").is_err());
+        assert!(parse_markup("<ctrl=1,2,123>").is_err());
+    }
+
+    #[test]
+    pub fn test_markup_roundtrip() {
+        let mut raw: Vec<u16> = "a < b \\ c > d\n".encode_utf16().collect();
+        raw.extend([0x0E, 0, 3, 2, 7]);
+        raw.extend([0x0E, 0, 3, 2, 0xFFFF]);
+        raw.extend([0x0E, 0, 3, 2, 2]);
+        raw.extend([0x0E, 0, 3, 2, 0xFFFF]);
+        raw.extend([0x0E, 3, 1, 2, 1]);
+        raw.extend([0x0E, 1, 0, 2, 30]);
+        raw.extend([0x0E, 1, 2, 4, 0, 0x1234]);
+        raw.extend([0x0E, 1, 6, 0]);
+        raw.extend([0x0E, 9, 9, 0]);
+        raw.extend([0x0F, 1, 2]);
+        raw.extend([0xD800, 0x0E, 5]);
+
+        let markup = to_markup(&parse_text(&raw));
+        assert_eq!(
+            markup,
+            "a \\< b \\\\ c > d\n<color=7></color><blue></blue><icon=B><pause=30>\
+            <ctrl=1,2,00001234><choice4><ctrl=9,9></ctrl=1,2><raw=D800>\u{e}\u{5}"
+        );
+        assert_eq!(write_text(&parse_markup(&markup).unwrap()), raw);
+    }
+}
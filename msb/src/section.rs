@@ -0,0 +1,197 @@
+//! sections other than the ones the parsers interpret themselves
+
+use std::{
+    io::{Seek, Write},
+    ops::Neg,
+};
+
+use binrw::{BinWriterExt, Endian};
+use serde::{Deserialize, Serialize};
+
+use crate::MsbError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExtraSection {
+    /// ATO1, one value per message
+    Ato1(Vec<u32>),
+    /// TSY1, index of the text style of each message
+    Tsy1(Vec<u32>),
+    /// NLI1, pairs of message id and message index
+    Nli1(Vec<(u32, u32)>),
+    /// any other section, kept as it is
+    Unknown {
+        #[serde(with = "crate::serialize::magic")]
+        magic: [u8; 4],
+        data: Vec<u8>,
+    },
+}
+
+impl ExtraSection {
+    pub fn magic(&self) -> [u8; 4] {
+        match self {
+            Self::Ato1(_) => *b"ATO1",
+            Self::Tsy1(_) => *b"TSY1",
+            Self::Nli1(_) => *b"NLI1",
+            Self::Unknown { magic, .. } => *magic,
+        }
+    }
+
+    /// interprets the section data, if the data doesn't have the expected layout
+    /// the section is kept as unknown, so that it is written back unchanged
+    pub fn parse(magic: [u8; 4], data: Vec<u8>, endian: Endian) -> Self {
+        let words = || -> Option<Vec<u32>> {
+            let chunks = data.chunks_exact(4);
+            if !chunks.remainder().is_empty() {
+                return None;
+            }
+            Some(
+                chunks
+                    .map(|w| u32_from_bytes(w.try_into().unwrap(), endian))
+                    .collect(),
+            )
+        };
+        let parsed = match &magic {
+            b"ATO1" => words().map(Self::Ato1),
+            b"TSY1" => words().map(Self::Tsy1),
+            b"NLI1" => words().and_then(|words| {
+                let (&count, entries) = words.split_first()?;
+                if entries.len() != count as usize * 2 {
+                    return None;
+                }
+                Some(Self::Nli1(
+                    entries.chunks_exact(2).map(|e| (e[0], e[1])).collect(),
+                ))
+            }),
+            _ => None,
+        };
+        parsed.unwrap_or(Self::Unknown { magic, data })
+    }
+
+    pub fn data(&self, endian: Endian) -> Vec<u8> {
+        let to_bytes = |v: u32| match endian {
+            Endian::Big => v.to_be_bytes(),
+            Endian::Little => v.to_le_bytes(),
+        };
+        match self {
+            Self::Ato1(values) | Self::Tsy1(values) => {
+                values.iter().flat_map(|v| to_bytes(*v)).collect()
+            }
+            Self::Nli1(entries) => std::iter::once(entries.len() as u32)
+                .chain(entries.iter().flat_map(|&(id, index)| [id, index]))
+                .flat_map(to_bytes)
+                .collect(),
+            Self::Unknown { data, .. } => data.clone(),
+        }
+    }
+
+    // stream position is at the end after it finished
+    pub(crate) fn write<WS: Write + Seek>(
+        &self,
+        ws: &mut WS,
+        endian: Endian,
+    ) -> Result<(), MsbError> {
+        write_section(ws, self.magic(), &self.data(endian), endian)
+    }
+}
+
+fn u32_from_bytes(bytes: [u8; 4], endian: Endian) -> u32 {
+    match endian {
+        Endian::Big => u32::from_be_bytes(bytes),
+        Endian::Little => u32::from_le_bytes(bytes),
+    }
+}
+
+/// writes a section header, the data and the padding after it
+pub(crate) fn write_section<WS: Write + Seek>(
+    ws: &mut WS,
+    magic: [u8; 4],
+    data: &[u8],
+    endian: Endian,
+) -> Result<(), MsbError> {
+    ws.write_all(&magic)?;
+    ws.write_type(&(data.len() as u32), endian)?;
+    ws.write_all(&[0u8; 8])?;
+    ws.write_all(data)?;
+    let pads = (data.len() as isize).neg().rem_euclid(16);
+    for _ in 0..pads {
+        ws.write_all(&[0xAB])?;
+    }
+    Ok(())
+}
+
+pub(crate) enum OrderedSection {
+    /// one of the sections the file type always has
+    Main([u8; 4]),
+    /// index into the extra sections
+    Extra(usize),
+}
+
+/// the order to write sections in, this is the order they were read in,
+/// sections that weren't read are written at the end
+pub(crate) fn section_order(
+    order: &[[u8; 4]],
+    main_sections: &[[u8; 4]],
+    extra_sections: &[ExtraSection],
+) -> Vec<OrderedSection> {
+    let mut used = vec![false; extra_sections.len()];
+    let mut result = Vec::new();
+    for magic in order {
+        if main_sections.contains(magic) {
+            result.push(OrderedSection::Main(*magic));
+            continue;
+        }
+        let extra = extra_sections
+            .iter()
+            .enumerate()
+            .position(|(i, section)| !used[i] && section.magic() == *magic);
+        if let Some(i) = extra {
+            used[i] = true;
+            result.push(OrderedSection::Extra(i));
+        }
+    }
+    for magic in main_sections {
+        if !order.contains(magic) {
+            result.push(OrderedSection::Main(*magic));
+        }
+    }
+    result.extend(
+        (0..extra_sections.len())
+            .filter(|i| !used[*i])
+            .map(OrderedSection::Extra),
+    );
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use binrw::Endian;
+
+    use super::ExtraSection;
+
+    #[test]
+    pub fn test_parse_sections() {
+        let nli1 = ExtraSection::parse(
+            *b"NLI1",
+            vec![0, 0, 0, 1, 0, 0, 0, 5, 0, 0, 0, 2],
+            Endian::Big,
+        );
+        assert_eq!(nli1, ExtraSection::Nli1(vec![(5, 2)]));
+        assert_eq!(
+            nli1.data(Endian::Big),
+            vec![0, 0, 0, 1, 0, 0, 0, 5, 0, 0, 0, 2]
+        );
+        assert_eq!(
+            nli1.data(Endian::Little),
+            vec![1, 0, 0, 0, 5, 0, 0, 0, 2, 0, 0, 0]
+        );
+        // doesn't match the count, kept as is
+        let broken = ExtraSection::parse(
+            *b"NLI1",
+            vec![0, 0, 0, 2, 0, 0, 0, 5, 0, 0, 0, 2],
+            Endian::Big,
+        );
+        assert!(matches!(broken, ExtraSection::Unknown { .. }));
+        let tsy1 = ExtraSection::parse(*b"TSY1", vec![0, 0, 0, 3, 1, 2], Endian::Big);
+        assert!(matches!(tsy1, ExtraSection::Unknown { .. }));
+    }
+}
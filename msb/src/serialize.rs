@@ -0,0 +1,345 @@
+//! serde support, meant for dumping files to YAML or JSON, editing them by hand and
+//! writing them back with `write_msbt`/`write_msbf`
+//!
+//! texts are written as markup, labels and flows as maps, so that the indices are visible
+
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    marker::PhantomData,
+};
+
+use binrw::Endian;
+use serde::{
+    de::{self, MapAccess, Visitor},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{ExtraSection, FlowEntry, LabelLayout, MsbHeader, Msbf, Msbt, TextSegment};
+
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Endian", rename_all = "lowercase")]
+pub(crate) enum EndianDef {
+    Big,
+    Little,
+}
+
+/// section magics as strings
+pub(crate) mod magic {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(magic: &[u8; 4], serializer: S) -> Result<S::Ok, S::Error> {
+        match std::str::from_utf8(magic) {
+            Ok(magic) => serializer.serialize_str(magic),
+            Err(_) => Err(serde::ser::Error::custom(format!(
+                "section magic {magic:02X?} is not valid UTF-8"
+            ))),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<[u8; 4], D::Error> {
+        let magic = String::deserialize(deserializer)?;
+        magic
+            .as_bytes()
+            .try_into()
+            .map_err(|_| de::Error::custom(format!("section magic {magic} is not 4 bytes long")))
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(transparent)]
+struct Magic(#[serde(with = "magic")] [u8; 4]);
+
+/// map that keeps the order of its entries
+struct OrderedMap<K, V>(Vec<(K, V)>);
+
+impl<K: Serialize, V: Serialize> Serialize for OrderedMap<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.0.iter().map(|(k, v)| (k, v)))
+    }
+}
+
+struct OrderedMapVisitor<K, V>(PhantomData<(K, V)>);
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for OrderedMapVisitor<K, V> {
+    type Value = OrderedMap<K, V>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+        let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some(entry) = map.next_entry()? {
+            entries.push(entry);
+        }
+        Ok(OrderedMap(entries))
+    }
+}
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for OrderedMap<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(OrderedMapVisitor(PhantomData))
+    }
+}
+
+/// labels in the order of the layout, labels that aren't in there are sorted by index
+fn ordered_labels<'a>(
+    labels: &'a HashMap<String, u32>,
+    layout: &'a LabelLayout,
+) -> OrderedMap<&'a str, u32> {
+    let mut seen = HashSet::new();
+    let mut ordered: Vec<(&str, u32)> = layout
+        .order
+        .iter()
+        .filter_map(|label| labels.get_key_value(label))
+        .filter(|(label, _)| seen.insert(label.as_str()))
+        .map(|(label, index)| (label.as_str(), *index))
+        .collect();
+    let mut new_labels: Vec<(&str, u32)> = labels
+        .iter()
+        .filter(|(label, _)| !seen.contains(label.as_str()))
+        .map(|(label, index)| (label.as_str(), *index))
+        .collect();
+    new_labels.sort_unstable_by_key(|&(label, index)| (index, label));
+    ordered.extend(new_labels);
+    OrderedMap(ordered)
+}
+
+fn label_map<E: de::Error>(
+    labels: OrderedMap<String, u32>,
+    bucket_count: u32,
+) -> Result<(HashMap<String, u32>, LabelLayout), E> {
+    let mut map = HashMap::with_capacity(labels.0.len());
+    let mut layout = LabelLayout::new(bucket_count);
+    for (label, index) in labels.0 {
+        if map.insert(label.clone(), index).is_some() {
+            return Err(E::custom(format!("duplicate label {label}")));
+        }
+        layout.order.push(label);
+    }
+    Ok((map, layout))
+}
+
+#[derive(Serialize)]
+struct TextSegmentRef<'a> {
+    text: String,
+    #[serde(skip_serializing_if = "<[u8]>::is_empty")]
+    atr: &'a [u8],
+}
+
+#[derive(Deserialize)]
+struct TextSegmentRepr {
+    text: String,
+    #[serde(default)]
+    atr: Vec<u8>,
+}
+
+impl Serialize for TextSegment {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TextSegmentRef {
+            text: self.markup(),
+            atr: &self.atr,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for TextSegment {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = TextSegmentRepr::deserialize(deserializer)?;
+        let mut segment = TextSegment {
+            atr: repr.atr,
+            text: Vec::new(),
+        };
+        segment.set_markup(&repr.text).map_err(de::Error::custom)?;
+        Ok(segment)
+    }
+}
+
+#[derive(Serialize)]
+struct MsbtRef<'a> {
+    header: &'a MsbHeader,
+    label_buckets: u32,
+    labels: OrderedMap<&'a str, u32>,
+    text: &'a [TextSegment],
+    #[serde(skip_serializing_if = "<[ExtraSection]>::is_empty")]
+    extra_sections: &'a [ExtraSection],
+    section_order: Vec<Magic>,
+}
+
+#[derive(Deserialize)]
+struct MsbtRepr {
+    header: MsbHeader,
+    label_buckets: u32,
+    labels: OrderedMap<String, u32>,
+    text: Vec<TextSegment>,
+    #[serde(default)]
+    extra_sections: Vec<ExtraSection>,
+    #[serde(default)]
+    section_order: Vec<Magic>,
+}
+
+impl Serialize for Msbt {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MsbtRef {
+            header: &self.header,
+            label_buckets: self.lbl_layout.bucket_count,
+            labels: ordered_labels(&self.lbl, &self.lbl_layout),
+            text: &self.text,
+            extra_sections: &self.extra_sections,
+            section_order: self.section_order.iter().copied().map(Magic).collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Msbt {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = MsbtRepr::deserialize(deserializer)?;
+        let (lbl, lbl_layout) = label_map(repr.labels, repr.label_buckets)?;
+        Ok(Msbt {
+            header: repr.header,
+            lbl,
+            lbl_layout,
+            text: repr.text,
+            extra_sections: repr.extra_sections,
+            section_order: repr.section_order.into_iter().map(|m| m.0).collect(),
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct MsbfRef<'a> {
+    header: &'a MsbHeader,
+    entrypoint_buckets: u32,
+    entrypoints: OrderedMap<&'a str, u32>,
+    flows: OrderedMap<usize, &'a FlowEntry>,
+    #[serde(skip_serializing_if = "<[ExtraSection]>::is_empty")]
+    extra_sections: &'a [ExtraSection],
+    section_order: Vec<Magic>,
+}
+
+#[derive(Deserialize)]
+struct MsbfRepr {
+    header: MsbHeader,
+    entrypoint_buckets: u32,
+    entrypoints: OrderedMap<String, u32>,
+    flows: OrderedMap<usize, FlowEntry>,
+    #[serde(default)]
+    extra_sections: Vec<ExtraSection>,
+    #[serde(default)]
+    section_order: Vec<Magic>,
+}
+
+impl Serialize for Msbf {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MsbfRef {
+            header: &self.header,
+            entrypoint_buckets: self.entrypoint_layout.bucket_count,
+            entrypoints: ordered_labels(&self.entrypoints, &self.entrypoint_layout),
+            flows: OrderedMap(self.flows.iter().enumerate().collect()),
+            extra_sections: &self.extra_sections,
+            section_order: self.section_order.iter().copied().map(Magic).collect(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Msbf {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let repr = MsbfRepr::deserialize(deserializer)?;
+        let (entrypoints, entrypoint_layout) =
+            label_map(repr.entrypoints, repr.entrypoint_buckets)?;
+        let mut flows = Vec::with_capacity(repr.flows.0.len());
+        for (index, flow) in repr.flows.0 {
+            // the indices are only there for readability, but they have to match
+            if index != flows.len() {
+                return Err(de::Error::custom(format!(
+                    "flow {index} is out of order, expected flow {}",
+                    flows.len()
+                )));
+            }
+            flows.push(flow);
+        }
+        Ok(Msbf {
+            header: repr.header,
+            flows,
+            entrypoints,
+            entrypoint_layout,
+            extra_sections: repr.extra_sections,
+            section_order: repr.section_order.into_iter().map(|m| m.0).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, io::Cursor};
+
+    use crate::{
+        parse_msbf, parse_msbt, ExtraSection, FlowEntry, LabelLayout, Msbf, Msbt, TextSegment,
+    };
+
+    #[test]
+    pub fn test_serde_roundtrip() {
+        let mut msbt = Msbt {
+            lbl: HashMap::from([("b".to_string(), 0), ("a".to_string(), 1)]),
+            lbl_layout: LabelLayout {
+                bucket_count: 31,
+                order: vec!["b".to_string(), "a".to_string()],
+            },
+            extra_sections: vec![ExtraSection::Unknown {
+                magic: *b"ABC1",
+                data: vec![1, 2, 3],
+            }],
+            ..Default::default()
+        };
+        msbt.text.push(TextSegment::default());
+        msbt.text[0].set_markup("Hello <red>Link</red>").unwrap();
+        msbt.text.push(TextSegment::default());
+        msbt.text[1].set_markup("<choice2>").unwrap();
+        let mut msbt_bytes = Cursor::new(Vec::new());
+        msbt.write_msbt(&mut msbt_bytes).unwrap();
+
+        let yaml = serde_yaml::to_string(&msbt).unwrap();
+        assert!(yaml.contains("text: Hello <red>Link</red>"));
+        let from_yaml: Msbt = serde_yaml::from_str(&yaml).unwrap();
+        let mut yaml_bytes = Cursor::new(Vec::new());
+        from_yaml.write_msbt(&mut yaml_bytes).unwrap();
+        assert_eq!(yaml_bytes.get_ref(), msbt_bytes.get_ref());
+
+        let parsed = parse_msbt(&mut msbt_bytes).unwrap();
+        let json = serde_json::to_string(&parsed).unwrap();
+        let from_json: Msbt = serde_json::from_str(&json).unwrap();
+        assert_eq!(from_json.lbl_layout, parsed.lbl_layout);
+        let mut json_bytes = Cursor::new(Vec::new());
+        from_json.write_msbt(&mut json_bytes).unwrap();
+        assert_eq!(json_bytes.get_ref(), msbt_bytes.get_ref());
+
+        let msbf = Msbf {
+            flows: vec![
+                FlowEntry::Start { next: 1 },
+                FlowEntry::Text {
+                    file: 0,
+                    line: 1,
+                    next: -1,
+                },
+            ],
+            entrypoints: HashMap::from([("100_00".to_string(), 0)]),
+            ..Default::default()
+        };
+        let mut msbf_bytes = Cursor::new(Vec::new());
+        msbf.write_msbf(&mut msbf_bytes).unwrap();
+        let yaml = serde_yaml::to_string(&parse_msbf(&mut msbf_bytes).unwrap()).unwrap();
+        assert!(yaml.contains("  1:\n    type: Text\n"));
+        let from_yaml: Msbf = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(from_yaml.flows, msbf.flows);
+        let json = serde_json::to_string(&msbf).unwrap();
+        let from_json: Msbf = serde_json::from_str(&json).unwrap();
+        assert_eq!(from_json.flows, msbf.flows);
+
+        // indices have to match the position
+        assert!(serde_yaml::from_str::<Msbf>(&yaml.replace("  1:\n", "  2:\n")).is_err());
+    }
+}
@@ -0,0 +1,207 @@
+//! parsed representation of TXT2 strings
+//!
+//! a string is a sequence of plain text and control sequences, a control sequence
+//! starts with 0x0E, followed by the group, the type, the size of the parameters in bytes
+//! and the parameters themselves
+
+use crate::TextSegment;
+
+pub(crate) const CONTROL_START: u16 = 0x0E;
+pub(crate) const CONTROL_END: u16 = 0x0F;
+
+/// the color control sequence with this color ends the colored text
+pub const COLOR_RESET: u16 = 0xFFFF;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextPart {
+    Text(String),
+    Control(ControlTag),
+    /// code units that are neither valid text nor a complete control sequence, kept as they are
+    Raw(Vec<u16>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ControlTag {
+    /// changes the text color to the color with this index in the color table,
+    /// [`COLOR_RESET`] switches back to the default color
+    Color(u16),
+    /// waits this many frames before the rest of the text is shown
+    Pause(u16),
+    /// shows the button icon with this index
+    Icon(u16),
+    /// lets the player choose one of the last 2 to 4 lines, the flow can branch on
+    /// the answer
+    Choice(u16),
+    /// any other control sequence, params are the raw parameter bytes
+    Other {
+        group: u16,
+        typ: u16,
+        params: Vec<u8>,
+    },
+    /// closing tag (0x0F) used by newer versions of the format
+    End { group: u16, typ: u16 },
+}
+
+impl ControlTag {
+    fn from_raw(group: u16, typ: u16, params: Vec<u8>) -> Self {
+        match (group, typ, params.as_slice()) {
+            (0, 3, &[hi, lo]) => ControlTag::Color(u16::from_be_bytes([hi, lo])),
+            (1, 0, &[hi, lo]) => ControlTag::Pause(u16::from_be_bytes([hi, lo])),
+            (3, 1, &[hi, lo]) => ControlTag::Icon(u16::from_be_bytes([hi, lo])),
+            // the type is the number of choices + 2
+            (1, 4..=6, &[]) => ControlTag::Choice(typ - 2),
+            _ => ControlTag::Other { group, typ, params },
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u16>) {
+        let (group, typ, params) = match self {
+            ControlTag::Color(color) => (0, 3, color.to_be_bytes().to_vec()),
+            ControlTag::Pause(frames) => (1, 0, frames.to_be_bytes().to_vec()),
+            ControlTag::Icon(icon) => (3, 1, icon.to_be_bytes().to_vec()),
+            ControlTag::Choice(count) => (1, count.wrapping_add(2), Vec::new()),
+            ControlTag::Other { group, typ, params } => (*group, *typ, params.clone()),
+            ControlTag::End { group, typ } => {
+                out.extend([CONTROL_END, *group, *typ]);
+                return;
+            }
+        };
+        out.extend([CONTROL_START, group, typ, params.len() as u16]);
+        out.extend(
+            params
+                .chunks(2)
+                .map(|c| u16::from_be_bytes([c[0], *c.get(1).unwrap_or(&0)])),
+        );
+    }
+}
+
+/// splits the raw string into text and control sequences
+pub fn parse_text(raw: &[u16]) -> Vec<TextPart> {
+    let mut parts = Vec::new();
+    let mut text_start = 0;
+    let mut pos = 0;
+    while pos < raw.len() {
+        let parsed = match raw[pos] {
+            CONTROL_START => parse_control(&raw[pos..]),
+            CONTROL_END if raw.len() >= pos + 3 => Some((
+                ControlTag::End {
+                    group: raw[pos + 1],
+                    typ: raw[pos + 2],
+                },
+                3,
+            )),
+            _ => None,
+        };
+        if let Some((tag, len)) = parsed {
+            push_text(&mut parts, &raw[text_start..pos]);
+            parts.push(TextPart::Control(tag));
+            pos += len;
+            text_start = pos;
+        } else {
+            pos += 1;
+        }
+    }
+    push_text(&mut parts, &raw[text_start..]);
+    parts
+}
+
+// returns the tag and how many code units it takes up
+fn parse_control(raw: &[u16]) -> Option<(ControlTag, usize)> {
+    let &[_, group, typ, size, ..] = raw else {
+        return None;
+    };
+    // the parameters are stored as whole code units, odd sizes are left as raw data
+    if size % 2 != 0 {
+        return None;
+    }
+    let param_units = raw.get(4..4 + size as usize / 2)?;
+    let params = param_units.iter().flat_map(|u| u.to_be_bytes()).collect();
+    Some((
+        ControlTag::from_raw(group, typ, params),
+        4 + param_units.len(),
+    ))
+}
+
+// unpaired surrogates can't be part of a String, they end up as raw parts
+fn push_text(parts: &mut Vec<TextPart>, units: &[u16]) {
+    for decoded in char::decode_utf16(units.iter().copied()) {
+        match (decoded, parts.last_mut()) {
+            (Ok(c), Some(TextPart::Text(text))) => text.push(c),
+            (Ok(c), _) => parts.push(TextPart::Text(c.into())),
+            (Err(e), Some(TextPart::Raw(raw))) => raw.push(e.unpaired_surrogate()),
+            (Err(e), _) => parts.push(TextPart::Raw(vec![e.unpaired_surrogate()])),
+        }
+    }
+}
+
+/// joins the parts back into a raw string, the inverse of [`parse_text`]
+pub fn write_text(parts: &[TextPart]) -> Vec<u16> {
+    let mut out = Vec::new();
+    for part in parts {
+        match part {
+            TextPart::Text(text) => out.extend(text.encode_utf16()),
+            TextPart::Control(tag) => tag.write(&mut out),
+            TextPart::Raw(units) => out.extend_from_slice(units),
+        }
+    }
+    out
+}
+
+impl TextSegment {
+    pub fn parts(&self) -> Vec<TextPart> {
+        parse_text(&self.text)
+    }
+
+    pub fn set_parts(&mut self, parts: &[TextPart]) {
+        self.text = write_text(parts);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_text, write_text, ControlTag, TextPart, COLOR_RESET};
+
+    #[test]
+    pub fn test_roundtrip() {
+        let mut raw: Vec<u16> = "Got the ".encode_utf16().collect();
+        raw.extend([0x0E, 0, 3, 2, 0]);
+        raw.extend("Goddess Sword".encode_utf16());
+        raw.extend([0x0E, 0, 3, 2, 0xFFFF]);
+        // parameters can contain 0
+        raw.extend([0x0E, 1, 2, 4, 0, 0x1234]);
+        raw.extend([0x0E, 1, 0, 2, 30, 0x0E, 3, 1, 2, 1, 0x0E, 1, 5, 0]);
+        // known tags with unexpected parameters stay untyped
+        raw.extend([0x0E, 1, 4, 2, 1]);
+        raw.push('!' as u16);
+        // unpaired surrogate and truncated control sequence
+        raw.extend([0xD800, 0x0E, 5]);
+
+        let parts = parse_text(&raw);
+        assert_eq!(
+            parts,
+            vec![
+                TextPart::Text("Got the ".into()),
+                TextPart::Control(ControlTag::Color(0)),
+                TextPart::Text("Goddess Sword".into()),
+                TextPart::Control(ControlTag::Color(COLOR_RESET)),
+                TextPart::Control(ControlTag::Other {
+                    group: 1,
+                    typ: 2,
+                    params: vec![0, 0, 0x12, 0x34]
+                }),
+                TextPart::Control(ControlTag::Pause(30)),
+                TextPart::Control(ControlTag::Icon(1)),
+                TextPart::Control(ControlTag::Choice(3)),
+                TextPart::Control(ControlTag::Other {
+                    group: 1,
+                    typ: 4,
+                    params: vec![0, 1]
+                }),
+                TextPart::Text("!".into()),
+                TextPart::Raw(vec![0xD800]),
+                TextPart::Text("\u{e}\u{5}".into()),
+            ]
+        );
+        assert_eq!(write_text(&parts), raw);
+    }
+}
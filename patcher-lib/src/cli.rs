@@ -4,13 +4,34 @@ use clap::Parser;
 
 #[derive(Debug, Parser)]
 pub enum Context {
-    FullExtract { vanilla_iso: PathBuf, modified_extract_dir: PathBuf,
+    FullExtract {
+        /// vanilla ISO or extracted game
+        vanilla_iso: PathBuf,
+        modified_extract_dir: PathBuf,
         #[clap(default_value = "0")]
         free_space_bytes: u32,
         single_stage: Option<String>,
+        /// maximum number of stages that are processed at the same time, defaults to the number of CPUs
+        #[clap(long)]
+        threads: Option<usize>,
+        /// directory with object archives (<name>.arc) to add to stages, falls back to the vanilla Object directory
+        #[clap(long)]
+        oarc_cache: Option<PathBuf>,
+        /// write the original and new sizes of all patched stage files as JSON to this path
+        #[clap(long)]
+        size_report: Option<PathBuf>,
     },
-    SingleStage { vanilla_iso: PathBuf, stage: String,
+    SingleStage {
+        /// vanilla ISO or extracted game
+        vanilla_iso: PathBuf,
+        stage: String,
         #[clap(default_value = "0")]
-        free_space_bytes: u32
+        free_space_bytes: u32,
+        /// directory with object archives (<name>.arc) to add to stages, falls back to the vanilla Object directory
+        #[clap(long)]
+        oarc_cache: Option<PathBuf>,
+        /// write the original and new sizes of all patched stage files as JSON to this path
+        #[clap(long)]
+        size_report: Option<PathBuf>,
     }
 }
@@ -0,0 +1,99 @@
+use std::{fs, io::Cursor, path::Path};
+
+use anyhow::Context;
+use log::{debug, info, warn};
+use msb::{
+    analysis::{analyze, Issue},
+    edit::EventPatcher,
+    parse_msbf, parse_msbt,
+};
+use u8file::U8File;
+
+use crate::{input::GameFiles, PatcherFunctions};
+
+/// directory of the event archives (`0-Common.arc`, `1-Town.arc`, ...), the region and
+/// language are fixed, so only the US release with English text is supported
+pub const EVENT_DIR: &str = "US/Object/en_US";
+
+/// runs the eventpatch hook for every msbf/msbt pair in the event archives and writes
+/// the modified archives to `DATA/files` in the output directory
+pub fn patch_events<F: PatcherFunctions, G: GameFiles + ?Sized>(
+    f: &F,
+    game: &G,
+    modified_extract_path: &Path,
+) -> anyhow::Result<()> {
+    for arc_name in game.list_files(EVENT_DIR)? {
+        if !arc_name.ends_with(".arc") {
+            continue;
+        }
+        let arc_path = format!("{EVENT_DIR}/{arc_name}");
+        let arc_data = game
+            .read_file(&arc_path)?
+            .with_context(|| format!("{arc_path} disappeared"))?;
+        let mut arc =
+            U8File::read(&arc_data).with_context(|| format!("read arc {arc_path} failed"))?;
+
+        if patch_event_arc(f, &mut arc).with_context(|| format!("patching {arc_path} failed"))? {
+            let mut buf = Vec::new();
+            arc.write(&mut Cursor::new(&mut buf))
+                .with_context(|| format!("writing arc for {arc_path} failed!"))?;
+            let out_path = modified_extract_path.join("DATA/files").join(&arc_path);
+            fs::write(&out_path, &buf)
+                .with_context(|| format!("writing {:?} failed", &out_path))?;
+        }
+    }
+    Ok(())
+}
+
+// returns if the arc was modified
+fn patch_event_arc<F: PatcherFunctions>(f: &F, arc: &mut U8File) -> anyhow::Result<bool> {
+    let mut is_modified = false;
+    for msbf_path in arc.get_all_paths() {
+        let Some(base_path) = msbf_path.strip_suffix(".msbf") else {
+            continue;
+        };
+        let msbt_path = format!("{base_path}.msbt");
+        // 003-ItemGet
+        let file_name = base_path.rsplit('/').next().unwrap_or(base_path);
+        let Some(msbt_data) = arc.get_entry_data(&msbt_path) else {
+            // events without text can't be patched with the EventPatcher
+            continue;
+        };
+        let msbt = parse_msbt(&mut Cursor::new(msbt_data))
+            .with_context(|| format!("failed to parse {msbt_path}"))?;
+        let msbf_data = arc.get_entry_data(&msbf_path).unwrap();
+        let msbf = parse_msbf(&mut Cursor::new(msbf_data))
+            .with_context(|| format!("failed to parse {msbf_path}"))?;
+
+        let mut patcher = EventPatcher::new(msbf, msbt);
+        if !f
+            .eventpatch(file_name, &mut patcher)
+            .with_context(|| format!("eventpatch for {file_name} failed"))?
+        {
+            continue;
+        }
+        info!("patched event {file_name}");
+
+        let (msbf, msbt) = patcher
+            .finish()
+            .with_context(|| format!("eventpatch for {file_name} is invalid"))?;
+        for issue in analyze(&msbf, Some(&msbt)) {
+            // vanilla events already have a few unused entries
+            if matches!(issue, Issue::Unreachable { .. }) {
+                debug!("{file_name}: {issue}");
+            } else {
+                warn!("{file_name}: {issue}");
+            }
+        }
+        let mut buf = Vec::new();
+        msbf.write_msbf(&mut Cursor::new(&mut buf))
+            .with_context(|| format!("writing {msbf_path} failed"))?;
+        arc.set_entry_data(&msbf_path, buf);
+        let mut buf = Vec::new();
+        msbt.write_msbt(&mut Cursor::new(&mut buf))
+            .with_context(|| format!("writing {msbt_path} failed"))?;
+        arc.set_entry_data(&msbt_path, buf);
+        is_modified = true;
+    }
+    Ok(is_modified)
+}
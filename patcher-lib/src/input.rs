@@ -0,0 +1,204 @@
+use std::{
+    fs::{self, File, FileType},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Context;
+use disc_riider::{structs::WiiPartType, Fst, FstNode, WiiIsoReader};
+
+/// read access to the files of the vanilla game
+///
+/// all paths are relative to the files directory of the DATA partition,
+/// for example `Stage/F000/F000_stg_l0.arc.LZ`
+pub trait GameFiles {
+    /// reads an entire file, returns None if it doesn't exist
+    fn read_file(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    /// returns the names of all directories inside the directory, sorted by name
+    fn list_dirs(&self, path: &str) -> anyhow::Result<Vec<String>>;
+
+    /// returns the names of all files inside the directory, sorted by name
+    fn list_files(&self, path: &str) -> anyhow::Result<Vec<String>>;
+}
+
+/// reads the game files directly from an ISO
+pub struct IsoInput {
+    // the disc can only be read by one thread at a time
+    iso: Mutex<WiiIsoReader<File>>,
+    fst: Fst,
+}
+
+impl IsoInput {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut iso = WiiIsoReader::create(File::open(path).context("could not open vanilla ISO")?)
+            .context("could not read vanilla ISO")?;
+        let mut data_section = iso
+            .open_partition_stream(&WiiPartType::Data)
+            .context("could not read DATA partition from ISO")?;
+        let mut data_reader = data_section.open_encryption_reader();
+        let section_header = data_reader
+            .read_disc_header()
+            .context("could not read vanilla ISO header, it might be corrupted?")?;
+        let fst = Fst::read(&mut data_reader, *section_header.fst_off)
+            .context("couldn't read vanilla ISO filesystem")?;
+        Ok(Self {
+            iso: Mutex::new(iso),
+            fst,
+        })
+    }
+}
+
+impl GameFiles for IsoInput {
+    fn read_file(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(&FstNode::File { offset, length, .. }) = self.fst.find_node_path(path) else {
+            return Ok(None);
+        };
+        let mut iso = self.iso.lock().unwrap();
+        let mut data_section = iso
+            .open_partition_stream(&WiiPartType::Data)
+            .context("could not read DATA partition from ISO")?;
+        let mut buf = Vec::new();
+        data_section
+            .open_encryption_reader()
+            .read_into_vec(offset, length.into(), &mut buf)
+            .with_context(|| format!("reading {path} failed"))?;
+        Ok(Some(buf))
+    }
+
+    fn list_dirs(&self, path: &str) -> anyhow::Result<Vec<String>> {
+        self.list_nodes(path, FstNode::is_dir)
+    }
+
+    fn list_files(&self, path: &str) -> anyhow::Result<Vec<String>> {
+        self.list_nodes(path, FstNode::is_file)
+    }
+}
+
+impl IsoInput {
+    fn list_nodes(&self, path: &str, filter: fn(&FstNode) -> bool) -> anyhow::Result<Vec<String>> {
+        let Some(FstNode::Directory { files, .. }) = self.fst.find_node_path(path) else {
+            anyhow::bail!("{path} is not a directory");
+        };
+        let mut names: Vec<String> = files
+            .iter()
+            .filter(|node| filter(node))
+            .map(|node| node.get_name().clone())
+            .collect();
+        names.sort_unstable();
+        Ok(names)
+    }
+}
+
+/// reads the game files from an extracted game
+pub struct ExtractInput {
+    files_dir: PathBuf,
+}
+
+impl ExtractInput {
+    /// path can either be the extract root (containing `DATA/files`), the partition root
+    /// (containing `files`) or the files directory itself, the files directory is
+    /// recognized by its `Stage` directory
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let Some(files_dir) = [
+            path.join("DATA/files"),
+            path.join("files"),
+            path.to_path_buf(),
+        ]
+        .into_iter()
+        .find(|p| p.join("Stage").is_dir()) else {
+            anyhow::bail!("{path:?} is not an extracted game, none of DATA/files/Stage, files/Stage or Stage exist in it");
+        };
+        Ok(Self { files_dir })
+    }
+}
+
+impl GameFiles for ExtractInput {
+    fn read_file(&self, path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let full_path = self.files_dir.join(path);
+        match fs::read(&full_path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e).with_context(|| format!("reading {full_path:?} failed")),
+        }
+    }
+
+    fn list_dirs(&self, path: &str) -> anyhow::Result<Vec<String>> {
+        self.list_entries(path, |file_type| file_type.is_dir())
+    }
+
+    fn list_files(&self, path: &str) -> anyhow::Result<Vec<String>> {
+        self.list_entries(path, |file_type| file_type.is_file())
+    }
+}
+
+impl ExtractInput {
+    fn list_entries(
+        &self,
+        path: &str,
+        filter: fn(&FileType) -> bool,
+    ) -> anyhow::Result<Vec<String>> {
+        let full_path = self.files_dir.join(path);
+        let mut names = Vec::new();
+        for entry in
+            fs::read_dir(&full_path).with_context(|| format!("can't read {full_path:?}"))?
+        {
+            let entry = entry.with_context(|| format!("can't read {full_path:?}"))?;
+            if filter(&entry.file_type()?) {
+                if let Some(name) = entry.file_name().to_str() {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort_unstable();
+        Ok(names)
+    }
+}
+
+/// opens either an ISO or an extracted game, depending on if the path is a file or a directory
+pub fn open_game(path: &Path) -> anyhow::Result<Box<dyn GameFiles + Sync>> {
+    if path.is_dir() {
+        Ok(Box::new(ExtractInput::open(path)?))
+    } else {
+        Ok(Box::new(IsoInput::open(path)?))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use super::{ExtractInput, GameFiles};
+
+    #[test]
+    pub fn test_extract_input() {
+        let root = std::env::temp_dir().join(format!(
+            "patcher-lib-test-extract-input-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("DATA/other")).unwrap();
+        // no Stage directory anywhere
+        assert!(ExtractInput::open(&root).is_err());
+        fs::create_dir_all(root.join("DATA/files/Stage/F000")).unwrap();
+        fs::create_dir_all(root.join("DATA/files/Stage/D000")).unwrap();
+        fs::write(root.join("DATA/files/Stage/F000/test.bin"), [1, 2, 3]).unwrap();
+
+        let input = ExtractInput::open(&root).unwrap();
+        assert_eq!(input.list_dirs("Stage").unwrap(), vec!["D000", "F000"]);
+        assert_eq!(input.list_files("Stage/F000").unwrap(), vec!["test.bin"]);
+        assert_eq!(
+            input.read_file("Stage/F000/test.bin").unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(input.read_file("Stage/F000/missing.bin").unwrap(), None);
+        // the partition root and the files directory work as well
+        let input = ExtractInput::open(&root.join("DATA")).unwrap();
+        assert_eq!(input.list_files("Stage/F000").unwrap(), vec!["test.bin"]);
+        let input = ExtractInput::open(&root.join("DATA/files")).unwrap();
+        assert_eq!(input.list_files("Stage/F000").unwrap(), vec!["test.bin"]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
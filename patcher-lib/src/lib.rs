@@ -1,21 +1,31 @@
 use std::{
     collections::{HashMap, HashSet},
-    fs::{self, File},
+    fs,
     io::Cursor,
-    path::Path,
+    path::{Path, PathBuf},
     str::FromStr, mem::MaybeUninit,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    thread,
 };
 
 use anyhow::{bail, Context};
 use bzs::{structs::{parse_bzs_file, write_bzs, BzsEntries}, edit::{ByIdExt, find_highest_used_id, ObjActorExt, InvalidPatchError}};
 use clap::Parser;
-use disc_riider::{Fst, FstNode};
+use events::patch_events;
+use input::{open_game, GameFiles};
 use log::info;
+use msb::edit::{EventPatcher, FlowPatchError};
+use oarc::{apply_oarc_changes, layers_with_changes, OarcLoader};
+use report::{SizeEntry, StageSizeReport, StageTooBigError};
 use stages::Stage;
 use u8file::{Entry, U8File};
 
 pub mod stages;
 mod cli;
+pub mod events;
+pub mod input;
+mod oarc;
+pub mod report;
 
 // this needs a better name
 pub trait PatcherFunctions {
@@ -28,87 +38,96 @@ pub trait PatcherFunctions {
         oarc_delete: &mut HashSet<(u8, &'static str)>) -> Result<bool, InvalidPatchError> {
             Ok(false)
         }
+
+    /// will be called for every event file (like `003-ItemGet`) that has both a msbf and a msbt,
+    /// should return true if something was changed
+    fn eventpatch(&self, _file_name: &str, _patcher: &mut EventPatcher) -> Result<bool, FlowPatchError> {
+        Ok(false)
+    }
 }
 
-pub fn handle<F: PatcherFunctions>(f: F) -> anyhow::Result<()> {
+pub fn handle<F: PatcherFunctions + Sync>(f: F) -> anyhow::Result<()> {
     let ctx = cli::Context::parse();
     match ctx {
-        cli::Context::FullExtract { vanilla_iso, modified_extract_dir, free_space_bytes, single_stage } => {
-            execute(&f, vanilla_iso, modified_extract_dir, free_space_bytes, single_stage)?;
+        cli::Context::FullExtract { vanilla_iso, modified_extract_dir, free_space_bytes, single_stage, oarc_cache, size_report, threads } => {
+            let game = open_game(&vanilla_iso)?;
+            let reports = execute(&f, game.as_ref(), modified_extract_dir, free_space_bytes, single_stage, oarc_cache, threads)?;
+            if let Some(size_report) = size_report {
+                write_size_report(&size_report, &reports)?;
+            }
         },
-        cli::Context::SingleStage { vanilla_iso: vanilla_iso_path, stage: name_str, free_space_bytes } => {
+        cli::Context::SingleStage { vanilla_iso, stage: name_str, free_space_bytes, oarc_cache, size_report } => {
             let _ = fs::create_dir_all("tmp");
 
-            let mut vanilla_iso = disc_riider::WiiIsoReader::create(
-                File::open(vanilla_iso_path).context("could not open vanilla ISO")?,
-            )
-            .context("could not read vanilla ISO")?;
-            let mut data_section = vanilla_iso
-                .open_partition_stream(&disc_riider::structs::WiiPartType::Data)
-                .context("could not read DATA partition from ISO")?;
-            let mut data_reader = data_section.open_encryption_reader();
-            let section_header = data_reader
-                .read_disc_header()
-                .context("could not read vanilla ISO header, it might be corrupted?")?;
-            let vanilla_fst = Fst::read(&mut data_reader, *section_header.fst_off)
-                .context("couldn't read vanilla ISO filesystem")?;
+            let game = open_game(&vanilla_iso)?;
+            let mut oarc_loader = OarcLoader::new(oarc_cache);
             
             let layer_0_filename = format!("Stage/{name_str}/{name_str}_stg_l0.arc.LZ");
 
-            let mut buf = Vec::new();
             let mut oarc_add = HashSet::new();
             let mut oarc_delete = HashSet::new();
-            let Some(FstNode::File { offset: layer_0_data_offset, length: layer_0_data_length, .. }) = vanilla_fst.find_node_path(&layer_0_filename) else {
-                bail!("couldn't find {layer_0_filename}");
-            };
             info!("Working on {name_str}...");
             let Ok(stage) = Stage::from_str(&name_str) else {
                 bail!("Stage {name_str} is not in the enum?!?!?");
             };
     
             // read arc
-            buf.clear();
-            data_reader
-                .read_into_vec(
-                    *layer_0_data_offset,
-                    (*layer_0_data_length).into(),
-                    &mut buf,
-                )
-                .context("read failed")?;
-
-            let decompressed_l0 = nlzss11::decompress(&buf)
-                .with_context(|| format!("decompress {} failed", name_str))?;
+            let Some(compressed) = game.read_file(&layer_0_filename)? else {
+                bail!("couldn't find {layer_0_filename}");
+            };
+
+            let mut arc = U8File::read_compressed(&compressed)
+                .with_context(|| format!("read arc {} failed", name_str))?;
             
-            let orig_len = decompressed_l0.len();
+            let orig_len = arc.get_data().len();
     
-            let is_modified = handle_single_stage(&mut buf, &decompressed_l0, &name_str, stage, &mut oarc_add, &mut oarc_delete, &f)?;
+            let (report, written_arc) = handle_single_stage(&mut arc, &name_str, stage, &mut oarc_add, &mut oarc_delete, &f, &mut oarc_loader, game.as_ref())?;
 
-            if !is_modified {
-                bail!("stage is not modified, can't write it");
+            if let Some(size_report) = size_report {
+                write_size_report(&size_report, std::slice::from_ref(&report))?;
             }
 
+            let Some(buf) = written_arc else {
+                bail!("stage is not modified, can't write it");
+            };
+
             if (orig_len + free_space_bytes as usize) < buf.len() {
-                bail!("new file is too big (needs {} bytes, has {} bytes)", buf.len(), orig_len + free_space_bytes as usize);
+                return Err(StageTooBigError {
+                    needed: buf.len(),
+                    available: orig_len + free_space_bytes as usize,
+                    report,
+                }
+                .into());
             }
 
             fs::write(Path::new("tmp/stage.arc"), &buf).context("failed to write tmp/stage.arc")?;
+
+            for (layer, layer_arc) in handle_stage_layers(&name_str, &oarc_add, &oarc_delete, &mut oarc_loader, game.as_ref())? {
+                let out_path = format!("tmp/stage_l{layer}.arc");
+                let mut buf = Vec::new();
+                layer_arc.write(&mut buf)
+                    .with_context(|| format!("writing arc for layer {layer} failed"))?;
+                fs::write(&out_path, &buf).with_context(|| format!("failed to write {out_path}"))?;
+            }
         }
     }
     Ok(())
 }
 
-// layer 0 as compressed data in buf
-// outfile in buf (if something changed, otherwise the content is unspecified)
-fn handle_single_stage<F: PatcherFunctions>(buf: &mut Vec<u8>, decompressed_l0: &[u8], name_str: &str, stage: Stage, oarc_add: &mut HashSet<(u8, &'static str)>, oarc_delete: &mut HashSet<(u8, &'static str)>, f: &F) -> anyhow::Result<bool> {
+// patches layer 0 of the stage
+// returns the uncompressed outfile if something changed, the report contains the sizes
+// of all rewritten files
+#[allow(clippy::too_many_arguments)]
+fn handle_single_stage<F: PatcherFunctions, G: GameFiles + ?Sized>(arc: &mut U8File, name_str: &str, stage: Stage, oarc_add: &mut HashSet<(u8, &'static str)>, oarc_delete: &mut HashSet<(u8, &'static str)>, f: &F, oarc_loader: &mut OarcLoader, game: &G) -> anyhow::Result<(StageSizeReport, Option<Vec<u8>>)> {
     let mut is_modified = false;
-
-    let mut arc = u8file::U8File::read(&decompressed_l0)
-        .with_context(|| format!("read arc {} failed", name_str))?;
+    let mut report = StageSizeReport::new(name_str, arc.get_data().len());
+    let mut buf = Vec::new();
 
     // first, process the bzs without rooms
     let bzs_data = arc
         .get_entry_data("dat/stage.bzs")
         .with_context(|| format!("stage not found in {}", &name_str))?;
+    let orig_bzs_len = bzs_data.len();
     let mut bzs = parse_bzs_file(&mut Cursor::new(&bzs_data))
         .with_context(|| format!("failed to parse stage bzs {}", &name_str))?;
 
@@ -117,9 +136,9 @@ fn handle_single_stage<F: PatcherFunctions>(buf: &mut Vec<u8>, decompressed_l0:
         is_modified = true;
     }
 
-    buf.clear();
-    write_bzs(&bzs, &mut Cursor::new(&mut *buf))
+    write_bzs(&bzs, &mut Cursor::new(&mut buf))
         .with_context(|| format!("writing bzs stage failed {:?}", &name_str))?;
+    report.entries.push(SizeEntry::new("dat/stage.bzs", orig_bzs_len, buf.len()));
     arc.set_entry_data("dat/stage.bzs", buf.clone());
 
     // process rooms
@@ -146,14 +165,20 @@ fn handle_single_stage<F: PatcherFunctions>(buf: &mut Vec<u8>, decompressed_l0:
     for room_id in &existing_rooms {
         // get bzs
         let room_filename = format!("rarc/{name_str}_r{room_id:02}.arc");
-        let room_arc_data = arc.get_entry_data(&room_filename).unwrap();
-        let mut room_arc = U8File::read(room_arc_data).with_context(|| {
-            format!("failed to parse arc room {room_id} {name_str}")
-        })?;
-        let room_bzs_data =
-            room_arc.get_entry_data("dat/room.bzs").with_context(|| {
+        let room_bzs_filename = format!("{room_filename}/dat/room.bzs");
+        let orig_room_arc_len = arc
+            .get_entry_data(&room_filename)
+            .with_context(|| format!("failed to find arc room {room_id} {name_str}"))?
+            .len();
+        let room_bzs_data = arc
+            .read_entry_data(&room_bzs_filename)
+            .with_context(|| {
+                format!("failed to parse arc room {room_id} {name_str}")
+            })?
+            .with_context(|| {
                 format!("failed to find room bzs in {room_id} {name_str}")
             })?;
+        let orig_room_bzs_len = room_bzs_data.len();
         let mut room_bzs = parse_bzs_file(&mut Cursor::new(&room_bzs_data))
             .with_context(|| {
                 format!("failed to parse room bzs in {room_id} {name_str}")
@@ -167,66 +192,88 @@ fn handle_single_stage<F: PatcherFunctions>(buf: &mut Vec<u8>, decompressed_l0:
 
         // write back
         buf.clear();
-        write_bzs(&room_bzs, &mut Cursor::new(&mut *buf))
+        write_bzs(&room_bzs, &mut Cursor::new(&mut buf))
             .with_context(|| format!("writing bzs for {name_str} {room_id} failed"))?;
-        room_arc.set_entry_data("dat/room.bzs", buf.clone());
+        let room_bzs_entry = SizeEntry::new(room_bzs_filename.clone(), orig_room_bzs_len, buf.len());
+        arc.set_entry_data(&room_bzs_filename, buf.clone());
 
-        buf.clear();
-        room_arc
-            .write(&mut Cursor::new(&mut *buf))
-            .with_context(|| format!("writing arc for {name_str} {room_id} failed"))?;
+        // the room arc is only written once, the stage arc uses the same data
+        let room_arc_len = arc
+            .close_nested(&room_filename)
+            .with_context(|| format!("writing arc for {name_str} {room_id} failed"))?
+            .with_context(|| format!("room arc {room_id} of {name_str} disappeared"))?
+            .len();
+        report.entries.push(SizeEntry::new(room_filename.clone(), orig_room_arc_len, room_arc_len));
+        report.entries.push(room_bzs_entry);
+    }
 
-        arc.set_entry_data(&room_filename, buf.clone());
+    // the oarcs of layer 0 are part of the same arc
+    if apply_oarc_changes(arc, 0, oarc_add, oarc_delete, oarc_loader, game)
+        .with_context(|| format!("failed to apply oarc changes to layer 0 of {name_str}"))? {
+        is_modified = true;
     }
 
-    if is_modified {
-        // write arc
-        buf.clear();
-        arc.write(&mut Cursor::new(&mut *buf))
-            .with_context(|| format!("writing arc for {name_str} failed!"))?;
-        Ok(true)
-    } else {
-        Ok(false)
+    if !is_modified {
+        return Ok((report, None));
     }
+    // write arc
+    buf.clear();
+    arc.write(&mut buf)
+        .with_context(|| format!("writing arc for {name_str} failed!"))?;
+    report.is_modified = true;
+    report.arc.new_size = buf.len();
+    Ok((report, Some(buf)))
+}
+
+fn write_size_report(path: &Path, reports: &[StageSizeReport]) -> anyhow::Result<()> {
+    let json = serde_json::to_string_pretty(reports).context("failed to serialize size report")?;
+    fs::write(path, json).with_context(|| format!("failed to write size report to {path:?}"))
 }
 
-fn execute<F: PatcherFunctions, V: AsRef<Path>, O: AsRef<Path>>(
+/// applies the oarc changes to all layers except layer 0 (that is handled together with the bzs)
+///
+/// returns the layer arcs that were modified, they keep the compression of the vanilla files
+fn handle_stage_layers<G: GameFiles + ?Sized>(name_str: &str, oarc_add: &HashSet<(u8, &'static str)>, oarc_delete: &HashSet<(u8, &'static str)>, oarc_loader: &mut OarcLoader, game: &G) -> anyhow::Result<Vec<(u8, U8File<'static>)>> {
+    let mut modified_layers = Vec::new();
+    for layer in layers_with_changes(oarc_add, oarc_delete) {
+        if layer == 0 {
+            continue;
+        }
+        let layer_filename = format!("Stage/{name_str}/{name_str}_stg_l{layer}.arc.LZ");
+        let Some(compressed) = game.read_file(&layer_filename)? else {
+            bail!("couldn't find {layer_filename}, can't change oarcs on layer {layer}");
+        };
+        let mut arc = U8File::read_compressed(&compressed)
+            .with_context(|| format!("read arc {layer_filename} failed"))?
+            .into_owned();
+        if apply_oarc_changes(&mut arc, layer, oarc_add, oarc_delete, oarc_loader, game)
+            .with_context(|| format!("failed to apply oarc changes to layer {layer} of {name_str}"))? {
+            modified_layers.push((layer, arc));
+        }
+    }
+    Ok(modified_layers)
+}
+
+/// patches all stages (or only a single one) of the game and writes the modified files
+/// to `DATA/files` in the output directory
+#[allow(clippy::too_many_arguments)]
+pub fn execute<F: PatcherFunctions + Sync, G: GameFiles + Sync + ?Sized, O: AsRef<Path>>(
     f: &F,
-    vanilla_iso_path: V,
+    game: &G,
     out_modified_dir: O,
     free_space_bytes: u32,
     single_stage_name: Option<String>,
-) -> anyhow::Result<()> {
+    oarc_cache: Option<PathBuf>,
+    thread_count: Option<usize>,
+) -> anyhow::Result<Vec<StageSizeReport>> {
     let single_stage = single_stage_name.map(|n| Stage::from_str(&n).with_context(|| format!("stagename {n} is invalid!"))).transpose()?;
     let modified_extract_path = out_modified_dir.as_ref();
-    let mut vanilla_iso = disc_riider::WiiIsoReader::create(
-        File::open(vanilla_iso_path.as_ref()).context("could not open vanilla ISO")?,
-    )
-    .context("could not read vanilla ISO")?;
-    let mut data_section = vanilla_iso
-        .open_partition_stream(&disc_riider::structs::WiiPartType::Data)
-        .context("could not read DATA partition from ISO")?;
-    let mut data_reader = data_section.open_encryption_reader();
-    let section_header = data_reader
-        .read_disc_header()
-        .context("could not read vanilla ISO header, it might be corrupted?")?;
-    let vanilla_fst = Fst::read(&mut data_reader, *section_header.fst_off)
-        .context("couldn't read vanilla ISO filesystem")?;
-
-    let FstNode::Directory { files: stage_dirs, .. } = vanilla_fst.find_node_path("Stage").context("can't find the Stage directory, is this a Skyward Sword ISO?")? else {
-        bail!("stages not a directory");
-    };
-    // let oarc_cache = Path::new("../../sslib/oarc/");
 
-    let mut buf = Vec::new();
-    for stage_dir in stage_dirs {
-        let mut oarc_add = HashSet::new();
-        let mut oarc_delete = HashSet::new();
-        let FstNode::Directory { name: name_str, files: stage_layer_files } = stage_dir else {
-            bail!("no stage dir");
-        };
-        info!("Working on {name_str}...");
-        let Ok(stage) = Stage::from_str(&name_str) else {
+    let stage_dirs = game.list_dirs("Stage").context("can't find the Stage directory, is this Skyward Sword?")?;
+    // collect the stages first, the actual work is spread over multiple threads
+    let mut jobs: Vec<(&str, Stage)> = Vec::with_capacity(stage_dirs.len());
+    for name_str in &stage_dirs {
+        let Ok(stage) = Stage::from_str(name_str) else {
             bail!("Stage {name_str} is not in the enum?!?!?");
         };
 
@@ -235,50 +282,171 @@ fn execute<F: PatcherFunctions, V: AsRef<Path>, O: AsRef<Path>>(
                 continue;
             }
         }
-        let l0_name = format!("{}_stg_l0.arc.LZ", name_str);
-        let Some(FstNode::File { offset: layer_0_data_offset, length: layer_0_data_length, .. }) = stage_layer_files.iter().find(|f| f.get_name() == &l0_name) else {
-            bail!("couldn't find l0");
-        };
+        jobs.push((name_str, stage));
+    }
 
-        // read arc
-        buf.clear();
-        data_reader
-            .read_into_vec(
-                *layer_0_data_offset,
-                (*layer_0_data_length).into(),
-                &mut buf,
-            )
-            .context("read failed")?;
-        // let mut f = File::open(&l0_path).with_context(|| format!("open {:?} failed", &l0_path))?;
-        // f.read_to_end(&mut buf)
-        //     .with_context(|| format!("read {:?} failed", &l0_path))?;
-
-        let decompressed_l0 = nlzss11::decompress(&buf)
-            .with_context(|| format!("decompress {} failed", name_str))?;
-
-        let is_modified = handle_single_stage(&mut buf, &decompressed_l0, name_str, stage, &mut oarc_add, &mut oarc_delete, f)?;
-
-        // TODO: copying could be done cheaper, if that's needed in the future
-        if is_modified {
-            if free_space_bytes > 0 {
-                buf.resize(buf.len() + free_space_bytes as usize, 0);
+    let thread_count = thread_count
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+        .clamp(1, jobs.len().max(1));
+    let next_job = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let mut results: Vec<(usize, anyhow::Result<StageSizeReport>)> = thread::scope(|s| {
+        let workers: Vec<_> = (0..thread_count)
+            .map(|_| {
+                s.spawn(|| {
+                    // every worker has its own cache, so they don't have to wait for each other
+                    let mut oarc_loader = OarcLoader::new(oarc_cache.clone());
+                    let mut results = Vec::new();
+                    while !failed.load(Ordering::Relaxed) {
+                        let job_idx = next_job.fetch_add(1, Ordering::Relaxed);
+                        let Some(&(name_str, stage)) = jobs.get(job_idx) else {
+                            break;
+                        };
+                        let result = process_stage(f, name_str, stage, modified_extract_path, free_space_bytes, &mut oarc_loader, game);
+                        if result.is_err() {
+                            failed.store(true, Ordering::Relaxed);
+                        }
+                        results.push((job_idx, result));
+                    }
+                    results
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap())
+            .collect()
+    });
+
+    // report in stage order (and the first error in stage order), regardless of which thread was faster
+    results.sort_unstable_by_key(|(job_idx, _)| *job_idx);
+    let reports = results.into_iter().map(|(_, result)| result).collect::<anyhow::Result<_>>()?;
+
+    // events are not part of a single stage
+    if single_stage.is_none() {
+        patch_events(f, game, modified_extract_path)?;
+    }
+    Ok(reports)
+}
+
+/// reads, patches and writes a single stage including the other layers
+fn process_stage<F: PatcherFunctions, G: GameFiles + ?Sized>(
+    f: &F,
+    name_str: &str,
+    stage: Stage,
+    modified_extract_path: &Path,
+    free_space_bytes: u32,
+    oarc_loader: &mut OarcLoader,
+    game: &G,
+) -> anyhow::Result<StageSizeReport> {
+    let mut oarc_add = HashSet::new();
+    let mut oarc_delete = HashSet::new();
+    info!("Working on {name_str}...");
+
+    // read arc
+    let Some(compressed) = game.read_file(&format!("Stage/{name_str}/{name_str}_stg_l0.arc.LZ"))? else {
+        bail!("couldn't find l0");
+    };
+
+    let mut arc = U8File::read_compressed(&compressed)
+        .with_context(|| format!("read arc {} failed", name_str))?;
+
+    let (report, written_arc) = handle_single_stage(&mut arc, name_str, stage, &mut oarc_add, &mut oarc_delete, f, oarc_loader, game)?;
+
+    // TODO: copying could be done cheaper, if that's needed in the future
+    if let Some(mut buf) = written_arc {
+        if free_space_bytes > 0 {
+            buf.resize(buf.len() + free_space_bytes as usize, 0);
+        }
+        // the free space is part of the uncompressed data, so this can't use write_compressed
+        let compressed = arc.compression().compress(buf);
+        let out_path = modified_extract_path.join(format!(
+            "DATA/files/Stage/{name_str}/{name_str}_stg_l0.arc.LZ"
+        ));
+        fs::write(&out_path, &compressed)
+            .with_context(|| format!("writing {:?} failed", &out_path))?;
+    }
+
+    for (layer, layer_arc) in handle_stage_layers(name_str, &oarc_add, &oarc_delete, oarc_loader, game)? {
+        let out_path = modified_extract_path.join(format!(
+            "DATA/files/Stage/{name_str}/{name_str}_stg_l{layer}.arc.LZ"
+        ));
+        layer_arc.save(&out_path)
+            .with_context(|| format!("writing {:?} failed", &out_path))?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, fs, io::Cursor};
+
+    use bzs::{edit::InvalidPatchError, structs::{write_bzs, BzsEntries}};
+    use u8file::{Compression, U8File};
+
+    use crate::{execute, input::ExtractInput, stages::Stage, PatcherFunctions};
+
+    struct AddOarc;
+
+    impl PatcherFunctions for AddOarc {
+        fn stagepatch(&self, _stage: Stage, room: Option<u8>,
+            _bzs: &mut BzsEntries,
+            oarc_add: &mut HashSet<(u8, &'static str)>,
+            _oarc_delete: &mut HashSet<(u8, &'static str)>) -> Result<bool, InvalidPatchError> {
+            if room.is_none() {
+                oarc_add.insert((0, "TestOarc"));
+                oarc_add.insert((1, "TestOarc"));
             }
-            let mut compressed = nlzss11::compress(&buf);
-            let out_path = modified_extract_path.join(format!(
-                "DATA/files/Stage/{name_str}/{name_str}_stg_l0.arc.LZ"
-            ));
-            // if single_stage.is_some() {
-            //     if out_path.exists() {
-            //         let target_filesize = out_path.metadata().context("failed to get metadata of out stage")?.len() as usize;
-            //         if target_filesize > compressed.len() {
-            //             bail!("new file is bigger than the old one, not replacing in single stage mode");
-            //         }
-            //         compressed.resize(target_filesize, 0);
-            //     }
-            // }
-            fs::write(&out_path, &compressed)
-                .with_context(|| format!("writing {:?} failed", &out_path))?;
+            Ok(false)
         }
     }
-    Ok(())
+
+    fn empty_bzs() -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_bzs(&BzsEntries::default(), &mut Cursor::new(&mut buf)).unwrap();
+        buf
+    }
+
+    #[test]
+    pub fn test_execute() {
+        let root = std::env::temp_dir().join(format!("patcher-lib-test-execute-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let stage_dir = root.join("vanilla/DATA/files/Stage/F000");
+        fs::create_dir_all(&stage_dir).unwrap();
+        fs::create_dir_all(root.join("vanilla/DATA/files/Object")).unwrap();
+        fs::create_dir_all(root.join("out/DATA/files/Stage/F000")).unwrap();
+
+        let mut room = U8File::new();
+        room.create_dir_all("dat").unwrap();
+        room.add_file("dat/room.bzs", empty_bzs()).unwrap();
+        let mut l0 = U8File::new();
+        l0.create_dir_all("dat").unwrap();
+        l0.add_file("dat/stage.bzs", empty_bzs()).unwrap();
+        l0.create_dir_all("rarc").unwrap();
+        l0.add_file("rarc/F000_r00.arc", room.write_compressed().unwrap()).unwrap();
+        l0.set_compression(Compression::Lz11);
+        l0.save(&stage_dir.join("F000_stg_l0.arc.LZ")).unwrap();
+        let mut l1 = U8File::new();
+        l1.set_compression(Compression::Lz11);
+        l1.save(&stage_dir.join("F000_stg_l1.arc.LZ")).unwrap();
+        fs::write(root.join("vanilla/DATA/files/Object/TestOarc.arc"), [1, 2, 3]).unwrap();
+
+        let game = ExtractInput::open(&root.join("vanilla")).unwrap();
+        let reports = execute(&AddOarc, &game, root.join("out"), 0, Some("F000".into()), None, Some(1)).unwrap();
+        assert_eq!(reports.len(), 1);
+        assert!(reports[0].is_modified);
+
+        let out_dir = root.join("out/DATA/files/Stage/F000");
+        for layer in [0, 1] {
+            let arc = U8File::open(&out_dir.join(format!("F000_stg_l{layer}.arc.LZ"))).unwrap();
+            assert_eq!(arc.compression(), Compression::Lz11);
+            assert_eq!(arc.get_entry_data("oarc/TestOarc.arc"), Some(&[1, 2, 3][..]));
+        }
+        let l0 = U8File::open(&out_dir.join("F000_stg_l0.arc.LZ")).unwrap();
+        assert_eq!(l0.get_entry_data("rarc/F000_r00.arc"), Some(&room.write_compressed().unwrap()[..]));
+        // layer 2 has no changes, so it isn't written
+        assert!(!out_dir.join("F000_stg_l2.arc.LZ").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
 }
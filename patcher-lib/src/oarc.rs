@@ -0,0 +1,186 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::PathBuf,
+};
+
+use anyhow::{bail, Context};
+use log::{info, warn};
+use u8file::{U8EditError, U8File};
+
+use crate::input::GameFiles;
+
+/// loads object archives (oarcs) that should be added to stages
+///
+/// oarcs are first looked up in the cache directory (if one is given) as `<name>.arc`,
+/// if they are not found there, they are read from the `Object/` directory of the vanilla game
+pub struct OarcLoader {
+    cache_dir: Option<PathBuf>,
+    loaded: HashMap<&'static str, Vec<u8>>,
+}
+
+impl OarcLoader {
+    pub fn new(cache_dir: Option<PathBuf>) -> Self {
+        Self {
+            cache_dir,
+            loaded: HashMap::new(),
+        }
+    }
+
+    /// returns the data of the oarc
+    pub fn get<G: GameFiles + ?Sized>(
+        &mut self,
+        name: &'static str,
+        game: &G,
+    ) -> anyhow::Result<&[u8]> {
+        if !self.loaded.contains_key(name) {
+            let data = self.load(name, game)?;
+            self.loaded.insert(name, data);
+        }
+        Ok(&self.loaded[name])
+    }
+
+    fn load<G: GameFiles + ?Sized>(&self, name: &str, game: &G) -> anyhow::Result<Vec<u8>> {
+        if let Some(cache_dir) = &self.cache_dir {
+            let cache_path = cache_dir.join(format!("{name}.arc"));
+            if cache_path.is_file() {
+                return fs::read(&cache_path)
+                    .with_context(|| format!("failed to read oarc {cache_path:?}"));
+            }
+        }
+        let vanilla_path = format!("Object/{name}.arc");
+        match game.read_file(&vanilla_path)? {
+            Some(data) => Ok(data),
+            None => bail!("oarc {name} is neither in the cache nor in the vanilla game"),
+        }
+    }
+}
+
+/// returns all layers that have oarc changes
+pub fn layers_with_changes(
+    oarc_add: &HashSet<(u8, &'static str)>,
+    oarc_delete: &HashSet<(u8, &'static str)>,
+) -> Vec<u8> {
+    let mut layers: Vec<u8> = oarc_add
+        .iter()
+        .chain(oarc_delete.iter())
+        .map(|(layer, _)| *layer)
+        .collect();
+    layers.sort_unstable();
+    layers.dedup();
+    layers
+}
+
+/// adds and removes the oarcs of the specified layer in the `oarc` directory of the layer arc
+///
+/// returns if the arc was modified
+pub fn apply_oarc_changes<G: GameFiles + ?Sized>(
+    arc: &mut U8File,
+    layer: u8,
+    oarc_add: &HashSet<(u8, &'static str)>,
+    oarc_delete: &HashSet<(u8, &'static str)>,
+    loader: &mut OarcLoader,
+    game: &G,
+) -> anyhow::Result<bool> {
+    let mut is_modified = false;
+
+    // sort, so that the result doesn't depend on the hash set iteration order
+    let mut to_add: Vec<&'static str> = oarc_add
+        .iter()
+        .filter(|(l, _)| *l == layer)
+        .map(|(_, name)| *name)
+        .collect();
+    to_add.sort_unstable();
+    let mut to_delete: Vec<&'static str> = oarc_delete
+        .iter()
+        .filter(|(l, _)| *l == layer)
+        .map(|(_, name)| *name)
+        .collect();
+    to_delete.sort_unstable();
+
+    if to_add.is_empty() && to_delete.is_empty() {
+        return Ok(false);
+    }
+
+    for name in to_delete {
+        match arc.remove(&format!("oarc/{name}.arc")) {
+            Ok(_) => {
+                info!("deleted oarc {name} on layer {layer}");
+                is_modified = true;
+            }
+            // without an oarc directory there's nothing to delete either
+            Err(U8EditError::NotFound(_) | U8EditError::MissingParent(_)) => {
+                warn!("oarc {name} doesn't exist on layer {layer}, can't delete it");
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to delete oarc {name} on layer {layer}"))
+            }
+        }
+    }
+
+    // only layers that get new oarcs need the directory
+    if !to_add.is_empty() {
+        arc.create_dir_all("oarc")
+            .with_context(|| format!("failed to create the oarc directory on layer {layer}"))?;
+    }
+
+    for name in to_add {
+        let path = format!("oarc/{name}.arc");
+        if arc.get_entry(&path).is_some() {
+            continue;
+        }
+        let data = loader.get(name, game)?.to_vec();
+        // new files are sorted like in the vanilla archives
+        arc.add_file(&path, data)
+            .with_context(|| format!("failed to add oarc {name} on layer {layer}"))?;
+        info!("added oarc {name} on layer {layer}");
+        is_modified = true;
+    }
+
+    Ok(is_modified)
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashSet;
+
+    use u8file::U8File;
+
+    use super::{apply_oarc_changes, OarcLoader};
+    use crate::input::GameFiles;
+
+    struct NoFiles;
+
+    impl GameFiles for NoFiles {
+        fn read_file(&self, _path: &str) -> anyhow::Result<Option<Vec<u8>>> {
+            Ok(None)
+        }
+
+        fn list_dirs(&self, _path: &str) -> anyhow::Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+
+        fn list_files(&self, _path: &str) -> anyhow::Result<Vec<String>> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[test]
+    pub fn test_delete_only() {
+        let mut arc = U8File::new();
+        let oarc_delete = HashSet::from([(0, "Missing")]);
+        let modified = apply_oarc_changes(
+            &mut arc,
+            0,
+            &HashSet::new(),
+            &oarc_delete,
+            &mut OarcLoader::new(None),
+            &NoFiles,
+        )
+        .unwrap();
+        assert!(!modified);
+        // deleting doesn't need the directory
+        assert!(arc.get_entry("oarc").is_none());
+    }
+}
@@ -0,0 +1,89 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+/// original and patched size of a single file inside a stage arc
+#[derive(Debug, Clone, Serialize)]
+pub struct SizeEntry {
+    pub path: String,
+    pub original_size: usize,
+    pub new_size: usize,
+}
+
+impl SizeEntry {
+    pub fn new(path: impl Into<String>, original_size: usize, new_size: usize) -> Self {
+        Self {
+            path: path.into(),
+            original_size,
+            new_size,
+        }
+    }
+
+    pub fn growth(&self) -> isize {
+        self.new_size as isize - self.original_size as isize
+    }
+}
+
+/// sizes of everything that was rewritten while patching a stage, to find out
+/// which patch made the stage arc grow
+#[derive(Debug, Clone, Serialize)]
+pub struct StageSizeReport {
+    pub stage: String,
+    pub is_modified: bool,
+    /// the uncompressed layer 0 arc
+    pub arc: SizeEntry,
+    /// stage.bzs, the room arcs and the room bzs, in that order
+    pub entries: Vec<SizeEntry>,
+}
+
+impl StageSizeReport {
+    pub fn new(stage: impl Into<String>, original_arc_size: usize) -> Self {
+        let stage = stage.into();
+        Self {
+            arc: SizeEntry::new(
+                format!("{stage}_stg_l0.arc"),
+                original_arc_size,
+                original_arc_size,
+            ),
+            stage,
+            is_modified: false,
+            entries: Vec::new(),
+        }
+    }
+}
+
+impl Display for StageSizeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{}: {} -> {} bytes ({:+})",
+            self.arc.path,
+            self.arc.original_size,
+            self.arc.new_size,
+            self.arc.growth()
+        )?;
+        let mut entries: Vec<_> = self.entries.iter().collect();
+        // biggest growth first, so the culprit is at the top
+        entries.sort_by_key(|e| -e.growth());
+        for entry in entries {
+            writeln!(
+                f,
+                "  {}: {} -> {} bytes ({:+})",
+                entry.path,
+                entry.original_size,
+                entry.new_size,
+                entry.growth()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// the patched stage arc doesn't fit into the available space
+#[derive(Debug, thiserror::Error)]
+#[error("new file for {} is too big (needs {needed} bytes, has {available} bytes)\n{report}", report.stage)]
+pub struct StageTooBigError {
+    pub needed: usize,
+    pub available: usize,
+    pub report: StageSizeReport,
+}
@@ -15,7 +15,6 @@ use crate::patches_gen::base_stage_patches;
 
 mod checks;
 mod checks_gen;
-mod eventpatches;
 mod options;
 // mod patches;
 mod patches_gen;
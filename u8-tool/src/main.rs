@@ -0,0 +1,44 @@
+use std::{fs::File, io::BufReader, path::PathBuf};
+
+use anyhow::Context;
+use clap::Parser;
+use u8file::{U8File, U8Reader};
+
+#[derive(Debug, Parser)]
+enum Cli {
+    /// print the paths of all files in an archive
+    List { arc: PathBuf },
+    /// extract all files of an archive to a directory, the entry order and the layout are
+    /// recorded next to them so that packing it again gives the same archive
+    Extract { arc: PathBuf, dir: PathBuf },
+    /// build an archive from a directory
+    Pack { dir: PathBuf, arc: PathBuf },
+}
+
+fn main() -> anyhow::Result<()> {
+    match Cli::parse() {
+        Cli::List { arc } => {
+            let file = File::open(&arc).with_context(|| format!("failed to open {arc:?}"))?;
+            let reader = U8Reader::new(BufReader::new(file))
+                .with_context(|| format!("failed to read {arc:?}"))?;
+            for path in reader.get_all_paths() {
+                println!("{path}");
+            }
+        }
+        Cli::Extract { arc, dir } => {
+            let u8file = U8File::open(&arc).with_context(|| format!("failed to read {arc:?}"))?;
+            u8file
+                .extract_dir(&dir)
+                .with_context(|| format!("failed to extract to {dir:?}"))?;
+        }
+        Cli::Pack { dir, arc } => {
+            let u8file =
+                U8File::pack_dir(&dir).with_context(|| format!("failed to pack {dir:?}"))?;
+            // compressed like the extracted archive
+            u8file
+                .save(&arc)
+                .with_context(|| format!("failed to write {arc:?}"))?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,376 @@
+use std::{borrow::Cow, fs, io, path::Path};
+
+use byteorder::{ByteOrder, BE, LE};
+
+use crate::{Entry, U8File, U8ParseError, MAGIC_HEADER};
+
+const YAZ0_MAGIC: &[u8; 4] = b"Yaz0";
+const YAZ0_HEADER_SIZE: usize = 0x10;
+const YAZ0_MIN_LENGTH: usize = 3;
+const YAZ0_MAX_LENGTH: usize = 0x111;
+const YAZ0_MAX_DISTANCE: usize = 0x1000;
+// the longest back reference takes 4 bytes
+const LZ11_MAX_LENGTH: usize = 0x10110;
+
+/// how an archive is compressed on disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Lz11,
+    Yaz0,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum CompressionError {
+    #[error("LZ11: {0}")]
+    Lz11(#[from] nlzss11::DecompressError),
+    #[error("Yaz0: unexpected EOF")]
+    UnexpectedEoF,
+    #[error("Yaz0: back reference before the start of the data")]
+    InvalidBackReference,
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum U8OpenError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("failed to decompress: {0}")]
+    Decompress(#[from] CompressionError),
+    #[error("failed to parse: {0}")]
+    Parse(#[from] U8ParseError),
+}
+
+impl Compression {
+    /// guesses the compression from the start of the data, uncompressed archives and
+    /// unknown data are `None`
+    pub fn detect(data: &[u8]) -> Self {
+        if data.starts_with(YAZ0_MAGIC) {
+            Self::Yaz0
+        } else if data.starts_with(&MAGIC_HEADER.to_be_bytes()) {
+            Self::None
+        } else if is_lz11(data) {
+            Self::Lz11
+        } else {
+            Self::None
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> Result<Cow<'_, [u8]>, CompressionError> {
+        Ok(match self {
+            Self::None => Cow::Borrowed(data),
+            Self::Lz11 => Cow::Owned(nlzss11::decompress(data)?),
+            Self::Yaz0 => Cow::Owned(yaz0_decompress(data)?),
+        })
+    }
+
+    pub fn compress(self, data: Vec<u8>) -> Vec<u8> {
+        match self {
+            Self::None => data,
+            Self::Lz11 => nlzss11::compress(&data),
+            Self::Yaz0 => yaz0_compress(&data),
+        }
+    }
+}
+
+impl<'a> U8File<'a> {
+    /// reads an archive that may be compressed with LZ11 or Yaz0, the compression is kept
+    /// for `write_compressed`
+    pub fn read_compressed(v: &'a [u8]) -> Result<Self, U8OpenError> {
+        let compression = Compression::detect(v);
+        let mut arc = match compression.decompress(v)? {
+            Cow::Borrowed(v) => U8File::read(v)?,
+            Cow::Owned(v) => U8File::read_owned(v)?,
+        };
+        arc.compression = compression;
+        Ok(arc)
+    }
+
+    /// reads a possibly compressed archive from a file
+    pub fn open(path: &Path) -> Result<U8File<'static>, U8OpenError> {
+        let data = fs::read(path)?;
+        let compression = Compression::detect(&data);
+        let data = match compression {
+            Compression::None => data,
+            _ => compression.decompress(&data)?.into_owned(),
+        };
+        let mut arc = U8File::read_owned(data)?;
+        arc.compression = compression;
+        Ok(arc)
+    }
+
+    pub fn compression(&self) -> Compression {
+        self.compression
+    }
+
+    pub fn set_compression(&mut self, compression: Compression) {
+        self.modified = true;
+        self.compression = compression;
+    }
+
+    /// writes the archive and compresses it the same way as the data it was read from
+    pub fn write_compressed(&self) -> io::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.write(&mut buf)?;
+        Ok(self.compression.compress(buf))
+    }
+
+    /// writes the archive to a file, compressed like `write_compressed`
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.write_compressed()?)
+    }
+
+    /// reads the file at the path as a possibly compressed archive,
+    /// None if it doesn't exist or is a directory
+    pub fn get_entry_archive(&self, path: &str) -> Result<Option<U8File<'_>>, U8OpenError> {
+        self.get_entry_data(path)
+            .map(U8File::read_compressed)
+            .transpose()
+    }
+
+    /// replaces the file at the path with the archive, compressed the same way it was
+    /// read, returns false if it doesn't exist or is a directory
+    pub fn set_entry_archive(&mut self, path: &str, arc: &U8File) -> io::Result<bool> {
+        if !matches!(self.get_entry(path), Some(Entry::FileEntry { .. })) {
+            return Ok(false);
+        }
+        Ok(self.set_entry_data(path, arc.write_compressed()?))
+    }
+}
+
+// 0x11 alone is too common, the header also has to fit the data after it
+fn is_lz11(data: &[u8]) -> bool {
+    if data.len() < 4 || data[0] != 0x11 {
+        return false;
+    }
+    // a size of 0 means it follows as a u32
+    let (size, header_len) = match LE::read_u24(&data[1..4]) as usize {
+        0 if data.len() >= 8 => (LE::read_u32(&data[4..8]) as usize, 8),
+        0 => return false,
+        size => (size, 4),
+    };
+    let Some(&flags) = data.get(header_len) else {
+        return false;
+    };
+    // the first block can't refer back to anything, and nothing can be bigger than
+    // only back references of the maximum length
+    size != 0
+        && flags & 0x80 == 0
+        && size <= (data.len() - header_len) / 4 * LZ11_MAX_LENGTH + LZ11_MAX_LENGTH
+}
+
+fn yaz0_decompress(data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+    if data.len() < YAZ0_HEADER_SIZE {
+        return Err(CompressionError::UnexpectedEoF);
+    }
+    let size = BE::read_u32(&data[4..8]) as usize;
+    let mut src = data[YAZ0_HEADER_SIZE..].iter().copied();
+    let mut next = || src.next().ok_or(CompressionError::UnexpectedEoF);
+    // the size comes from the header, so it's only trusted as far as the data goes,
+    // the buffer still grows if it really is compressed that well
+    let mut out = Vec::with_capacity(size.min(data.len() * 8));
+    while out.len() < size {
+        let header = next()?;
+        for bit in (0..8).rev() {
+            if out.len() >= size {
+                break;
+            }
+            if header & (1 << bit) != 0 {
+                out.push(next()?);
+                continue;
+            }
+            // NDDD DDDD DDDD, length N + 2, or a third byte + 0x12 if N is 0
+            let first = next()?;
+            let distance = ((first as usize & 0xF) << 8 | next()? as usize) + 1;
+            let length = match first >> 4 {
+                0 => next()? as usize + 0x12,
+                n => n as usize + 2,
+            };
+            let Some(start) = out.len().checked_sub(distance) else {
+                return Err(CompressionError::InvalidBackReference);
+            };
+            // the source can overlap with what is written
+            for i in start..start + length {
+                out.push(out[i]);
+            }
+        }
+    }
+    out.truncate(size);
+    Ok(out)
+}
+
+fn yaz0_compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(YAZ0_HEADER_SIZE + data.len());
+    out.extend(YAZ0_MAGIC);
+    out.extend((data.len() as u32).to_be_bytes());
+    out.extend([0; 8]);
+    let mut searcher = MatchSearcher::new(data.len());
+    let mut pos = 0;
+    while pos < data.len() {
+        let header_pos = out.len();
+        out.push(0);
+        for bit in (0..8).rev() {
+            if pos >= data.len() {
+                break;
+            }
+            match searcher.find(data, pos) {
+                Some((length, distance)) => {
+                    let distance = distance - 1;
+                    if length >= 0x12 {
+                        out.push((distance >> 8) as u8);
+                        out.push(distance as u8);
+                        out.push((length - 0x12) as u8);
+                    } else {
+                        out.push(((length - 2) << 4 | distance >> 8) as u8);
+                        out.push(distance as u8);
+                    }
+                    for p in pos..pos + length {
+                        searcher.insert(data, p);
+                    }
+                    pos += length;
+                }
+                None => {
+                    out[header_pos] |= 1 << bit;
+                    out.push(data[pos]);
+                    searcher.insert(data, pos);
+                    pos += 1;
+                }
+            }
+        }
+    }
+    out
+}
+
+/// finds back references with hash chains over the next 3 bytes
+struct MatchSearcher {
+    head: Vec<usize>,
+    prev: Vec<usize>,
+}
+
+impl MatchSearcher {
+    const HASH_BITS: u32 = 15;
+    const MAX_CHAIN: usize = 256;
+    const NONE: usize = usize::MAX;
+
+    fn new(len: usize) -> Self {
+        MatchSearcher {
+            head: vec![Self::NONE; 1 << Self::HASH_BITS],
+            prev: vec![Self::NONE; len],
+        }
+    }
+
+    fn hash(data: &[u8], pos: usize) -> usize {
+        let value = u32::from_be_bytes([0, data[pos], data[pos + 1], data[pos + 2]]);
+        (value.wrapping_mul(0x9E3779B1) >> (32 - Self::HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, data: &[u8], pos: usize) {
+        if pos + YAZ0_MIN_LENGTH > data.len() {
+            return;
+        }
+        let hash = Self::hash(data, pos);
+        self.prev[pos] = self.head[hash];
+        self.head[hash] = pos;
+    }
+
+    /// longest match as (length, distance)
+    fn find(&self, data: &[u8], pos: usize) -> Option<(usize, usize)> {
+        if pos + YAZ0_MIN_LENGTH > data.len() {
+            return None;
+        }
+        let max_length = YAZ0_MAX_LENGTH.min(data.len() - pos);
+        let mut best = (0, 0);
+        let mut candidate = self.head[Self::hash(data, pos)];
+        for _ in 0..Self::MAX_CHAIN {
+            if candidate == Self::NONE || pos - candidate > YAZ0_MAX_DISTANCE {
+                break;
+            }
+            let length = data[candidate..]
+                .iter()
+                .zip(&data[pos..pos + max_length])
+                .take_while(|(a, b)| a == b)
+                .count();
+            if length > best.0 {
+                best = (length, pos - candidate);
+                if length == max_length {
+                    break;
+                }
+            }
+            candidate = self.prev[candidate];
+        }
+        (best.0 >= YAZ0_MIN_LENGTH).then_some(best)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{yaz0_compress, yaz0_decompress, Compression, CompressionError};
+    use crate::U8File;
+
+    #[test]
+    pub fn test_compression() {
+        // 2 literals followed by a back reference of length 4 and distance 2
+        let compressed = b"Yaz0\0\0\0\x06\0\0\0\0\0\0\0\0\xC0ab\x20\x01";
+        assert_eq!(yaz0_decompress(compressed).unwrap(), b"ababab");
+        assert_eq!(yaz0_compress(b"ababab"), compressed);
+
+        let data: Vec<u8> = (0..0x3000u32).map(|i| (i * i / 7 % 13) as u8).collect();
+        for compression in [Compression::None, Compression::Lz11, Compression::Yaz0] {
+            let compressed = compression.compress(data.clone());
+            assert_eq!(Compression::detect(&compressed), compression);
+            assert_eq!(compression.decompress(&compressed).unwrap(), data);
+        }
+
+        // starts like LZ11, but the header doesn't fit the rest
+        assert_eq!(
+            Compression::detect(b"\x11\0\0\0\0\0\0\0\0a"),
+            Compression::None
+        );
+        assert_eq!(
+            Compression::detect(b"\x11\x10\0\0\x80\x20\x01"),
+            Compression::None
+        );
+        assert_eq!(
+            Compression::detect(b"\x11\xFF\xFF\xFF\0ab"),
+            Compression::None
+        );
+        // a huge size in the header isn't allocated up front
+        let truncated = b"Yaz0\xFF\xFF\xFF\xFF\0\0\0\0\0\0\0\0\xC0ab";
+        assert!(matches!(
+            yaz0_decompress(truncated),
+            Err(CompressionError::UnexpectedEoF)
+        ));
+    }
+
+    #[test]
+    pub fn test_nested_archive() {
+        let mut room = U8File::new();
+        room.create_dir_all("dat").unwrap();
+        room.add_file("dat/room.bzs", vec![1; 0x40]).unwrap();
+        room.set_compression(Compression::Yaz0);
+        let mut stage = U8File::new();
+        stage.create_dir_all("rarc").unwrap();
+        stage
+            .add_file("rarc/room.arc", room.write_compressed().unwrap())
+            .unwrap();
+        stage.set_compression(Compression::Lz11);
+        let written = stage.write_compressed().unwrap();
+
+        let mut stage = U8File::read_compressed(&written).unwrap();
+        assert_eq!(stage.compression(), Compression::Lz11);
+        let mut room = stage.get_entry_archive("rarc/room.arc").unwrap().unwrap();
+        assert_eq!(room.compression(), Compression::Yaz0);
+        assert_eq!(room.get_entry_data("dat/room.bzs"), Some(&[1; 0x40][..]));
+        room.set_entry_data("dat/room.bzs", vec![2; 3]);
+        let room = room.into_owned();
+        assert!(stage.set_entry_archive("rarc/room.arc", &room).unwrap());
+        assert!(!stage.set_entry_archive("rarc", &room).unwrap());
+
+        let written = stage.write_compressed().unwrap();
+        let stage = U8File::read_compressed(&written).unwrap();
+        let room = stage.get_entry_archive("rarc/room.arc").unwrap().unwrap();
+        assert_eq!(room.compression(), Compression::Yaz0);
+        assert_eq!(room.get_entry_data("dat/room.bzs"), Some(&[2; 3][..]));
+        assert!(stage.get_entry_archive("missing.arc").unwrap().is_none());
+    }
+}
@@ -0,0 +1,475 @@
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    fmt::Write,
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::{Compression, Entry, FileEntry, U8EditError, U8File, U8Layout, MAGIC_HEADER};
+
+/// name of the file that records the entry order of an extracted archive
+pub const MANIFEST_NAME: &str = ".u8manifest";
+
+/// name of the file that records the bytes around the file data of an extracted archive,
+/// so that packing it again gives the same bytes
+pub const LAYOUT_NAME: &str = ".u8layout";
+
+#[derive(thiserror::Error, Debug)]
+pub enum U8DirError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Edit(#[from] U8EditError),
+    #[error("{0} is in the manifest but doesn't exist")]
+    MissingFile(String),
+    #[error("invalid layout: {0}")]
+    InvalidLayout(String),
+}
+
+impl U8File<'_> {
+    /// writes all entries to the directory, together with a manifest of the entry order
+    /// and the layout of the archive
+    pub fn extract_dir(&self, dir: &Path) -> Result<(), U8DirError> {
+        fs::create_dir_all(dir)?;
+        self.extract_rec(&self.root, dir)?;
+        fs::write(dir.join(MANIFEST_NAME), self.manifest())?;
+        if let Some(layout) = self.layout_description()? {
+            fs::write(dir.join(LAYOUT_NAME), layout)?;
+        }
+        Ok(())
+    }
+
+    fn extract_rec(&self, files: &[Entry], dir: &Path) -> Result<(), U8DirError> {
+        for entry in files {
+            let name = entry.get_name();
+            // names like this would end up outside of the directory
+            if name == "." || name == ".." || name.contains(['/', '\\']) {
+                return Err(U8EditError::InvalidName(name.clone()).into());
+            }
+            let path = dir.join(name);
+            match entry {
+                Entry::DirEntry { files, .. } => {
+                    fs::create_dir_all(&path)?;
+                    self.extract_rec(files, &path)?;
+                }
+                Entry::FileEntry { .. } => {
+                    fs::write(&path, self.get_file_bytes(entry)?)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// all paths in the order of the archive, one per line, directories end with "/"
+    pub fn manifest(&self) -> String {
+        let mut manifest = String::new();
+        manifest_rec("", &self.root, &mut manifest);
+        manifest
+    }
+
+    /// the bytes of the written archive that aren't nodes, names or file data, with the
+    /// offset of every file, one value per line, None if the layout is too unusual to be
+    /// reproduced
+    pub fn layout_description(&self) -> io::Result<Option<String>> {
+        let mut written = Vec::new();
+        self.write(&mut written)?;
+        let Ok(arc) = U8File::read(&written) else {
+            return Ok(None);
+        };
+        let Some(layout) = &arc.layout else {
+            return Ok(None);
+        };
+        let bytes = |start: u32, end: u32| to_hex(&written[start as usize..end as usize]);
+        let mut description = String::new();
+        let _ = writeln!(
+            description,
+            "header {}",
+            bytes(0x10, layout.first_node_offset)
+        );
+        let _ = writeln!(description, "data_offset {:#x}", layout.data_offset);
+        let _ = writeln!(
+            description,
+            "gap {}",
+            bytes(layout.string_pool_end, layout.data_offset)
+        );
+        let mut files = Vec::new();
+        layout_files_rec("", &arc.root, &mut files);
+        for (path, offset, length) in files {
+            let padding = bytes(layout.padding_starts[&offset], offset);
+            let _ = writeln!(description, "file {offset:#x} {length:#x} {padding} {path}");
+        }
+        let _ = writeln!(
+            description,
+            "trailing {}",
+            bytes(layout.data_end, written.len() as u32)
+        );
+        match self.compression {
+            Compression::None => {}
+            Compression::Lz11 => description.push_str("compression lz11\n"),
+            Compression::Yaz0 => description.push_str("compression yaz0\n"),
+        }
+        Ok(Some(description))
+    }
+
+    /// builds an archive from all files in the directory
+    ///
+    /// entries are in the order of the manifest if the directory has one, entries that
+    /// aren't in it come after the others, sorted by name, unlike `add_file` they aren't
+    /// inserted in the order of the game's archives
+    ///
+    /// if the directory has a layout, the archive is written with it, files that still
+    /// have their original length keep their original offset and padding
+    pub fn pack_dir(dir: &Path) -> Result<U8File<'static>, U8DirError> {
+        let mut arc = U8File::new();
+        match fs::read_to_string(dir.join(MANIFEST_NAME)) {
+            Ok(manifest) => {
+                for line in manifest.lines().filter(|line| !line.is_empty()) {
+                    // the same paths that can't be extracted, they would read outside of
+                    // the directory
+                    let path = line.strip_suffix('/').unwrap_or(line);
+                    if line.starts_with('/')
+                        || Path::new(line).is_absolute()
+                        || line.contains('\\')
+                        || path.split('/').any(|part| part == "." || part == "..")
+                    {
+                        return Err(U8EditError::InvalidName(line.to_string()).into());
+                    }
+                    if let Some(dir_path) = line.strip_suffix('/') {
+                        if !dir.join(dir_path).is_dir() {
+                            return Err(U8DirError::MissingFile(line.to_string()));
+                        }
+                        arc.create_dir_all_in_order(dir_path, false)?;
+                    } else {
+                        let data = fs::read(dir.join(line)).map_err(|e| match e.kind() {
+                            io::ErrorKind::NotFound => U8DirError::MissingFile(line.to_string()),
+                            _ => e.into(),
+                        })?;
+                        if let Some((parent, _)) = line.rsplit_once('/') {
+                            arc.create_dir_all_in_order(parent, false)?;
+                        }
+                        arc.add_file_in_order(line, data, false)?;
+                    }
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        arc.pack_rec(dir, "")?;
+        match fs::read_to_string(dir.join(LAYOUT_NAME)) {
+            Ok(layout) => arc.apply_layout(&layout)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(arc)
+    }
+
+    // rebuilds the parts of the original data that are written again, the data of files
+    // with an unchanged length is put back at its original offset
+    fn apply_layout(&mut self, description: &str) -> Result<(), U8DirError> {
+        let invalid = |line: &str| U8DirError::InvalidLayout(line.to_string());
+        let mut header = Vec::new();
+        let mut data_offset = None;
+        let mut gap = Vec::new();
+        let mut files = HashMap::new();
+        let mut trailing = Vec::new();
+        let mut compression = Compression::None;
+        for line in description.lines().filter(|line| !line.is_empty()) {
+            let (key, value) = line.split_once(' ').ok_or_else(|| invalid(line))?;
+            match key {
+                "header" => header = from_hex(value).ok_or_else(|| invalid(line))?,
+                "data_offset" => {
+                    data_offset = Some(parse_offset(value).ok_or_else(|| invalid(line))?)
+                }
+                "gap" => gap = from_hex(value).ok_or_else(|| invalid(line))?,
+                "file" => {
+                    let mut parts = value.splitn(4, ' ');
+                    let mut next = || parts.next().ok_or_else(|| invalid(line));
+                    let offset = parse_offset(next()?).ok_or_else(|| invalid(line))?;
+                    let length = parse_offset(next()?).ok_or_else(|| invalid(line))?;
+                    let padding = from_hex(next()?).ok_or_else(|| invalid(line))?;
+                    files.insert(next()?, (offset, length, padding));
+                }
+                "trailing" => trailing = from_hex(value).ok_or_else(|| invalid(line))?,
+                "compression" => {
+                    compression = match value {
+                        "lz11" => Compression::Lz11,
+                        "yaz0" => Compression::Yaz0,
+                        _ => return Err(invalid(line)),
+                    }
+                }
+                _ => return Err(invalid(line)),
+            }
+        }
+        let data_offset = data_offset.ok_or_else(|| invalid("data_offset is missing"))?;
+        let first_node_offset = 0x10 + header.len() as u32;
+        let string_pool_end = data_offset
+            .checked_sub(gap.len() as u32)
+            .filter(|&end| end >= first_node_offset)
+            .ok_or_else(|| invalid("the gap doesn't fit before the data offset"))?;
+
+        let mut data = Vec::new();
+        put_bytes(&mut data, 0, &MAGIC_HEADER.to_be_bytes());
+        put_bytes(&mut data, 4, &first_node_offset.to_be_bytes());
+        put_bytes(
+            &mut data,
+            8,
+            &(string_pool_end - first_node_offset).to_be_bytes(),
+        );
+        put_bytes(&mut data, 12, &data_offset.to_be_bytes());
+        put_bytes(&mut data, 0x10, &header);
+        put_bytes(&mut data, string_pool_end, &gap);
+        let mut ranges = Vec::new();
+        let mut data_end = data_offset;
+        for (path, (offset, length, padding)) in &files {
+            let padding_start = offset
+                .checked_sub(padding.len() as u32)
+                .ok_or_else(|| invalid(path))?;
+            put_bytes(&mut data, padding_start, padding);
+            ranges.push((*offset, *length));
+            data_end = data_end.max(offset.checked_add(*length).ok_or_else(|| invalid(path))?);
+        }
+        put_bytes(&mut data, data_end, &trailing);
+        // files that still fit at their original offset keep the padding before them
+        files_rec("", &mut self.root, &mut |path, file| {
+            let Some(&(offset, length, _)) = files.get(path) else {
+                return;
+            };
+            if let FileEntry::Data(file_data) = file {
+                if file_data.len() == length as usize {
+                    put_bytes(&mut data, offset, file_data);
+                    *file = FileEntry::Ref { offset, length };
+                }
+            }
+        });
+
+        self.layout = U8Layout::from_ranges(&data, ranges);
+        self.data = Cow::Owned(data);
+        self.compression = compression;
+        Ok(())
+    }
+
+    fn pack_rec(&mut self, dir: &Path, prefix: &str) -> Result<(), U8DirError> {
+        let mut paths = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<PathBuf>, _>>()?;
+        paths.sort();
+        for path in paths {
+            let file_name = path.file_name().unwrap();
+            let Some(name) = file_name.to_str() else {
+                return Err(U8EditError::InvalidName(file_name.to_string_lossy().into()).into());
+            };
+            if prefix.is_empty() && (name == MANIFEST_NAME || name == LAYOUT_NAME) {
+                continue;
+            }
+            let arc_path = format!("{prefix}{name}");
+            if path.is_dir() {
+                self.create_dir_all_in_order(&arc_path, false)?;
+                self.pack_rec(&path, &format!("{arc_path}/"))?;
+            } else if self.get_entry(&arc_path).is_none() {
+                self.add_file_in_order(&arc_path, fs::read(&path)?, false)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// the path, offset and length of every file
+fn layout_files_rec(prefix: &str, files: &[Entry], layout_files: &mut Vec<(String, u32, u32)>) {
+    for entry in files {
+        let path = format!("{prefix}{}", entry.get_name());
+        match entry {
+            Entry::DirEntry { files, .. } => {
+                layout_files_rec(&format!("{path}/"), files, layout_files)
+            }
+            &Entry::FileEntry {
+                data: FileEntry::Ref { offset, length },
+                ..
+            } => layout_files.push((path, offset, length)),
+            Entry::FileEntry { .. } => {}
+        }
+    }
+}
+
+fn files_rec(prefix: &str, files: &mut [Entry], f: &mut impl FnMut(&str, &mut FileEntry)) {
+    for entry in files {
+        match entry {
+            Entry::DirEntry { name, files } => files_rec(&format!("{prefix}{name}/"), files, f),
+            Entry::FileEntry { name, data } => f(&format!("{prefix}{name}"), data),
+        }
+    }
+}
+
+// writes the bytes at the offset, the data is extended with zeros if needed
+fn put_bytes(data: &mut Vec<u8>, offset: u32, bytes: &[u8]) {
+    let offset = offset as usize;
+    if data.len() < offset + bytes.len() {
+        data.resize(offset + bytes.len(), 0);
+    }
+    data[offset..offset + bytes.len()].copy_from_slice(bytes);
+}
+
+// empty byte strings are written as "-", so that every value has at least one character
+fn to_hex(bytes: &[u8]) -> String {
+    if bytes.is_empty() {
+        return "-".to_string();
+    }
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex == "-" {
+        return Some(Vec::new());
+    }
+    // an odd length leaves half a byte at the end, which fails to parse
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn parse_offset(value: &str) -> Option<u32> {
+    u32::from_str_radix(value.strip_prefix("0x")?, 16).ok()
+}
+
+fn manifest_rec(prefix: &str, files: &[Entry], manifest: &mut String) {
+    for entry in files {
+        manifest.push_str(prefix);
+        manifest.push_str(entry.get_name());
+        if let Entry::DirEntry { files, .. } = entry {
+            manifest.push_str("/\n");
+            manifest_rec(&format!("{prefix}{}/", entry.get_name()), files, manifest);
+        } else {
+            manifest.push('\n');
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use crate::{
+        Compression, Entry, FileEntry, U8DirError, U8EditError, U8File, U8WriteOptions,
+        LAYOUT_NAME, MANIFEST_NAME,
+    };
+
+    #[test]
+    pub fn test_extract_pack() {
+        let mut arc = U8File::new();
+        arc.create_dir_all("rarc").unwrap();
+        arc.create_dir_all("empty").unwrap();
+        arc.add_file("z.bin", vec![1]).unwrap();
+        arc.add_file("rarc/b.arc", vec![2, 3]).unwrap();
+        arc.add_file("rarc/a.arc", vec![4]).unwrap();
+        // the manifest keeps any order, not only the sorted one of new entries
+        arc.get_root_entry_mut().reverse();
+        let Some(Entry::DirEntry { files, .. }) = arc.get_entry_mut("rarc") else {
+            unreachable!();
+        };
+        files.reverse();
+        let mut original = Vec::new();
+        arc.write(&mut original).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("u8file-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let arc = U8File::read(&original).unwrap();
+        arc.extract_dir(&dir).unwrap();
+        assert_eq!(fs::read(dir.join("rarc/b.arc")).unwrap(), vec![2, 3]);
+        assert_eq!(
+            fs::read_to_string(dir.join(MANIFEST_NAME)).unwrap(),
+            "z.bin\nrarc/\nrarc/b.arc\nrarc/a.arc\nempty/\n"
+        );
+
+        let mut repacked = Vec::new();
+        U8File::pack_dir(&dir)
+            .unwrap()
+            .write(&mut repacked)
+            .unwrap();
+        assert_eq!(repacked, original);
+
+        // without the manifest, everything is sorted and new files are added
+        fs::remove_file(dir.join(MANIFEST_NAME)).unwrap();
+        fs::write(dir.join("rarc/c.arc"), [5]).unwrap();
+        let packed = U8File::pack_dir(&dir).unwrap();
+        assert_eq!(
+            packed.manifest(),
+            "empty/\nrarc/\nrarc/a.arc\nrarc/b.arc\nrarc/c.arc\nz.bin\n"
+        );
+
+        // paths in the manifest can't leave the directory
+        for line in ["../z.bin", "rarc/./a.arc", "/etc/passwd", "rarc/../"] {
+            fs::write(dir.join(MANIFEST_NAME), line).unwrap();
+            assert!(matches!(
+                U8File::pack_dir(&dir),
+                Err(U8DirError::Edit(U8EditError::InvalidName(_)))
+            ));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    pub fn test_extract_pack_layout() {
+        let mut arc = U8File::new();
+        arc.create_dir_all("dat").unwrap();
+        arc.add_file("dat/a.bin", vec![1; 3]).unwrap();
+        arc.add_file("dat/b.bin", vec![2; 5]).unwrap();
+        arc.add_file("c.bin", vec![3; 2]).unwrap();
+        let options = U8WriteOptions { alignment: Some(4) };
+        let mut original = Vec::new();
+        arc.write_with_options(&mut original, &options).unwrap();
+        // garbage in the header, the gap after the string pool and the padding, and
+        // some trailing bytes
+        let data_offset = u32::from_be_bytes(original[12..16].try_into().unwrap()) as usize;
+        let string_pool_end = 0x20 + u32::from_be_bytes(original[8..12].try_into().unwrap());
+        assert!((string_pool_end as usize) < data_offset);
+        original[0x10..0x20].fill(0xCC);
+        original[string_pool_end as usize..data_offset].fill(0xBB);
+        let b_offset = match U8File::read(&original).unwrap().get_entry("dat/b.bin") {
+            Some(&Entry::FileEntry {
+                data: FileEntry::Ref { offset, .. },
+                ..
+            }) => offset as usize,
+            _ => unreachable!(),
+        };
+        original[b_offset - 1] = 0xDD;
+        original.extend([0xEE; 3]);
+        let arc = U8File::read_owned(original.clone()).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("u8file-test-layout-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        arc.extract_dir(&dir).unwrap();
+        let mut repacked = Vec::new();
+        U8File::pack_dir(&dir)
+            .unwrap()
+            .write(&mut repacked)
+            .unwrap();
+        assert_eq!(repacked, original);
+
+        // changed files still get the original alignment, the bytes after the original
+        // data don't fit anymore
+        fs::write(dir.join("dat/a.bin"), [4; 6]).unwrap();
+        let packed = U8File::pack_dir(&dir).unwrap();
+        let mut repacked = Vec::new();
+        packed.write(&mut repacked).unwrap();
+        assert_eq!(&repacked[0x10..0x20], &[0xCC; 16]);
+        assert!(repacked.ends_with(&[4, 4, 4, 4, 4, 4, 0, 0, 2, 2, 2, 2, 2]));
+
+        // the compression is kept as well
+        let mut arc = arc;
+        arc.set_compression(Compression::Yaz0);
+        let compressed = arc.write_compressed().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        arc.extract_dir(&dir).unwrap();
+        let packed = U8File::pack_dir(&dir).unwrap();
+        assert_eq!(packed.compression(), Compression::Yaz0);
+        assert_eq!(packed.write_compressed().unwrap(), compressed);
+
+        fs::write(dir.join(LAYOUT_NAME), "header -\nfile 0x10 zz dat/a.bin\n").unwrap();
+        assert!(matches!(
+            U8File::pack_dir(&dir),
+            Err(U8DirError::InvalidLayout(_))
+        ));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,10 +1,19 @@
 use std::{
-    borrow::{Borrow, BorrowMut},
-    io::{Cursor, Read, Seek, SeekFrom, Write},
+    borrow::Cow,
+    collections::HashMap,
+    io::{self, Cursor, Read, Seek, SeekFrom, Write},
     ops::Neg,
 };
 
-use byteorder::{ReadBytesExt, WriteBytesExt, BE};
+use byteorder::{ByteOrder, ReadBytesExt, WriteBytesExt, BE};
+
+mod compression;
+mod dir;
+mod reader;
+
+pub use compression::{Compression, CompressionError, U8OpenError};
+pub use dir::{U8DirError, LAYOUT_NAME, MANIFEST_NAME};
+pub use reader::U8Reader;
 
 #[derive(Debug)]
 pub enum Entry {
@@ -14,13 +23,31 @@ pub enum Entry {
 
 #[derive(Debug)]
 pub enum FileEntry {
-    Ref { offset: u32, length: u32 },
+    Ref {
+        offset: u32,
+        length: u32,
+    },
     Data(Vec<u8>),
+    /// a nested archive that was opened through a path, if it was modified it's written
+    /// again with its own compression when the outer archive is written
+    Archive {
+        archive: Box<U8File<'static>>,
+        /// the file before it was opened, a `Ref` or `Data`, written as long as the
+        /// archive isn't modified
+        original: Box<FileEntry>,
+    },
 }
 
+#[derive(Debug)]
 pub struct U8File<'a> {
-    data: &'a [u8],
+    data: Cow<'a, [u8]>,
     root: Vec<Entry>,
+    /// None for new archives and archives with an unusual layout
+    layout: Option<U8Layout>,
+    compression: Compression,
+    /// if anything was changed since the archive was read, nested archives only have to
+    /// be written again if this is set for them or for one of their own nested archives
+    modified: bool,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -35,6 +62,22 @@ pub enum U8ParseError {
     InvalidNodeDecoding,
 }
 
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum U8EditError {
+    #[error("{0} doesn't exist")]
+    NotFound(String),
+    #[error("parent directory of {0} doesn't exist")]
+    MissingParent(String),
+    #[error("{0} is not a directory")]
+    NotADirectory(String),
+    #[error("{0} already exists")]
+    AlreadyExists(String),
+    #[error("invalid name {0:?}, names must be non empty ASCII without '/'")]
+    InvalidName(String),
+    #[error("can't move {0} into itself")]
+    MoveIntoItself(String),
+}
+
 impl From<std::io::Error> for U8ParseError {
     fn from(_: std::io::Error) -> Self {
         U8ParseError::UnexpectedEoF
@@ -111,6 +154,16 @@ fn read_ascii<RS: Read + Seek>(data: &mut RS, pos: u32) -> Result<String, U8Pars
     Ok(String::from_utf8(buf).unwrap())
 }
 
+impl FileEntry {
+    /// the original file for opened archives that weren't modified
+    fn unopened(&self) -> &FileEntry {
+        match self {
+            FileEntry::Archive { archive, original } if !archive.is_modified() => original,
+            _ => self,
+        }
+    }
+}
+
 impl Entry {
     pub fn is_dir(&self) -> bool {
         matches!(self, Entry::DirEntry { .. })
@@ -136,142 +189,415 @@ impl Entry {
         )
     }
 
+    pub fn is_archive(&self) -> bool {
+        matches!(
+            self,
+            Entry::FileEntry {
+                data: FileEntry::Archive { .. },
+                ..
+            }
+        )
+    }
+
     pub fn get_name(&self) -> &String {
         match self {
             Self::DirEntry { name, .. } => name,
             Self::FileEntry { name, .. } => name,
         }
     }
+
+    fn name_mut(&mut self) -> &mut String {
+        match self {
+            Self::DirEntry { name, .. } => name,
+            Self::FileEntry { name, .. } => name,
+        }
+    }
 }
 
-pub const MAGIC_HEADER: u32 = 0x55AA382D;
+/// splits a path into its parts, a starting "/" is ignored
+fn split_path(path: &str) -> Vec<&str> {
+    let path = path.strip_prefix('/').unwrap_or(path);
+    if path.is_empty() {
+        Vec::new()
+    } else {
+        path.split('/').collect()
+    }
+}
 
-impl<'a> U8File<'a> {
-    /// reads a byte Vector into an U8File or returns an Error
-    pub fn read(v: &'a [u8]) -> Result<Self, U8ParseError> {
-        let mut c = Cursor::new(v);
-        let header = c.read_u32::<BE>()?;
-        if MAGIC_HEADER != header {
-            return Err(U8ParseError::InvalidMagic);
+fn check_name(name: &str) -> Result<(), U8EditError> {
+    if name.is_empty() || !name.is_ascii() || name.contains(['/', '\0']) {
+        return Err(U8EditError::InvalidName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// returns the data of the archive the files are in and the files of the directory, or of
+/// the root of the nested archive, which is opened
+fn enter_dir<'b>(
+    data: &'b [u8],
+    entry: &'b mut Entry,
+    path: &str,
+) -> Result<(&'b [u8], &'b mut Vec<Entry>), U8EditError> {
+    match entry {
+        Entry::DirEntry { files, .. } => Ok((data, files)),
+        Entry::FileEntry { data: file, .. } => {
+            let U8File { data, root, .. } = open_file_entry(data, file)
+                .map_err(|_| U8EditError::NotADirectory(path.to_string()))?;
+            Ok((data, root))
         }
-        let first_node_offset = c.read_u32::<BE>()?;
+    }
+}
 
-        let first_node = read_raw_node(&mut c, first_node_offset)?;
+/// sorted entries are inserted before the first one with a greater name, ignoring case,
+/// which is the order the game's archives use, others are added at the end
+///
+/// returns the index of the entry
+fn insert_entry(files: &mut Vec<Entry>, entry: Entry, sorted: bool) -> usize {
+    let index = if sorted {
+        let name = entry.get_name().to_ascii_lowercase();
+        files
+            .iter()
+            .position(|other| other.get_name().to_ascii_lowercase() > name)
+            .unwrap_or(files.len())
+    } else {
+        files.len()
+    };
+    files.insert(index, entry);
+    index
+}
 
-        let total_node_count = match first_node {
-            RawNode::RawDirNode {
-                next_parent_index, ..
-            } => next_parent_index,
-            _ => return Err(U8ParseError::InvalidNode),
-        };
+pub const MAGIC_HEADER: u32 = 0x55AA382D;
 
-        let string_pool_offset = first_node_offset + total_node_count * 12;
+/// alignment of the file data in new archives
+const DEFAULT_ALIGNMENT: u32 = 0x20;
 
-        // let root_node_name = read_ascii(&mut c, string_pool_offset)?;
+impl Default for U8File<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-        let root = read_nodes_recursive(
-            &mut c,
-            1,
-            total_node_count,
-            first_node_offset,
-            string_pool_offset,
-        )?;
+impl<'a> U8File<'a> {
+    /// creates an empty archive
+    pub fn new() -> Self {
+        U8File {
+            data: Cow::Borrowed(&[]),
+            root: Vec::new(),
+            layout: None,
+            compression: Compression::None,
+            modified: false,
+        }
+    }
+
+    /// reads a byte Vector into an U8File or returns an Error
+    pub fn read(v: &'a [u8]) -> Result<Self, U8ParseError> {
+        let root = read_root(&mut Cursor::new(v))?;
+        let layout = U8Layout::read(v, &root);
 
         Ok(U8File {
             root,
-            data: c.into_inner(),
+            data: Cow::Borrowed(v),
+            layout,
+            compression: Compression::None,
+            modified: false,
         })
     }
 
+    /// like `read`, but the archive owns the data
+    pub fn read_owned(v: Vec<u8>) -> Result<U8File<'static>, U8ParseError> {
+        let root = read_root(&mut Cursor::new(&v))?;
+        let layout = U8Layout::read(&v, &root);
+
+        Ok(U8File {
+            root,
+            data: Cow::Owned(v),
+            layout,
+            compression: Compression::None,
+            modified: false,
+        })
+    }
+
+    /// copies the data if it's borrowed, so that the archive can outlive it
+    pub fn into_owned(self) -> U8File<'static> {
+        U8File {
+            data: Cow::Owned(self.data.into_owned()),
+            root: self.root,
+            layout: self.layout,
+            compression: self.compression,
+            modified: self.modified,
+        }
+    }
+
+    /// if anything in the archive or in its opened nested archives was changed since it
+    /// was read, everything that gives mutable access counts as a change
+    pub fn is_modified(&self) -> bool {
+        self.modified || any_archive_modified(&self.root)
+    }
+
     pub fn get_root_entry(&self) -> &Vec<Entry> {
         &self.root
     }
 
+    pub fn get_root_entry_mut(&mut self) -> &mut Vec<Entry> {
+        self.modified = true;
+        &mut self.root
+    }
+
     pub fn get_data(&self) -> &[u8] {
         &self.data
     }
 
+    /// returns the data of the file at the path, paths can go through nested archives
+    /// that are already opened, None for directories and opened nested archives that
+    /// were modified
     pub fn get_entry_data<'b>(&'b self, path: &str) -> Option<&'b [u8]> {
-        self.get_entry(path)
-            .and_then(|entry| self.get_data_from_entry(entry))
+        find_entry_in(&self.data, &self.root, &split_path(path))
+            .and_then(|(data, entry)| get_data_in(data, entry))
     }
 
-    pub fn set_entry_data(&mut self, path: &str, new_data: Vec<u8>) -> bool {
-        self.get_entry_mut(path).map_or(false, |entry| match entry {
-            Entry::DirEntry { .. } => false,
-            Entry::FileEntry { data, .. } => {
-                *data = FileEntry::Data(new_data);
-                true
+    /// like `get_entry_data`, but opens the nested archives in the path first
+    pub fn read_entry_data<'b>(&'b mut self, path: &str) -> Result<Option<&'b [u8]>, U8OpenError> {
+        find_entry_mut(&self.data, &mut self.root, &split_path(path), false)?;
+        Ok(self.get_entry_data(path))
+    }
+
+    /// parses the file at the path as a nested archive, it's kept parsed so that changes
+    /// to it end up in this archive when it's written, None if the path doesn't exist
+    /// or is a directory
+    pub fn open_nested<'b>(
+        &'b mut self,
+        path: &str,
+    ) -> Result<Option<&'b mut U8File<'static>>, U8OpenError> {
+        match find_entry_mut(&self.data, &mut self.root, &split_path(path), false)? {
+            Some((data, Entry::FileEntry { data: file, .. })) => {
+                Ok(Some(open_file_entry(data, file)?))
             }
-        })
+            _ => Ok(None),
+        }
     }
 
-    pub fn get_data_from_offset_len(&self, offset: u32, length: u32) -> &[u8] {
-        &self.data[offset as usize..][..length as usize]
+    /// turns the nested archive at the path back into a file, it's written once if it was
+    /// modified, returns the data of the file, None if the path doesn't exist or is
+    /// a directory
+    pub fn close_nested<'b>(&'b mut self, path: &str) -> io::Result<Option<&'b [u8]>> {
+        if !self.get_entry(path).is_some_and(Entry::is_archive) {
+            return Ok(self.get_entry_data(path));
+        }
+        if let Some(Entry::FileEntry { data: file, .. }) = self.get_entry_mut(path) {
+            let closed = match file {
+                FileEntry::Archive { archive, .. } if archive.is_modified() => {
+                    FileEntry::Data(archive.write_compressed()?)
+                }
+                FileEntry::Archive { original, .. } => {
+                    std::mem::replace(&mut **original, FileEntry::Data(Vec::new()))
+                }
+                _ => unreachable!(),
+            };
+            *file = closed;
+        }
+        Ok(self.get_entry_data(path))
     }
 
-    pub fn get_data_from_entry<'b>(&'b self, entry: &'b Entry) -> Option<&'b [u8]> {
-        match entry {
-            Entry::DirEntry { .. } => None,
+    pub fn set_entry_data(&mut self, path: &str, new_data: Vec<u8>) -> bool {
+        // directories aren't changed, so they don't count as modified
+        let parts = split_path(path);
+        if !matches!(
+            find_entry_mut(&self.data, &mut self.root, &parts, false),
+            Ok(Some((_, Entry::FileEntry { .. })))
+        ) {
+            return false;
+        }
+        if let Some(Entry::FileEntry { data, .. }) = self.get_entry_mut(path) {
+            *data = FileEntry::Data(new_data);
+        }
+        true
+    }
+
+    /// adds a new file, the parent directory has to exist already
+    ///
+    /// new entries are inserted sorted by name ignoring case, like in the game's archives,
+    /// this also applies to `create_dir_all` and `move_entry`
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) -> Result<(), U8EditError> {
+        self.add_file_in_order(path, data, true)
+    }
+
+    /// returns the parent directory of the path and the name of the entry, nested archives
+    /// in the path are opened, use `mark_modified` once something was changed
+    fn get_parent_mut<'b, 'p>(
+        &'b mut self,
+        path: &'p str,
+    ) -> Result<(&'b mut Vec<Entry>, &'p str), U8EditError> {
+        let parts = split_path(path);
+        let Some((name, parent)) = parts.split_last() else {
+            return Err(U8EditError::InvalidName(path.to_string()));
+        };
+        let mut data: &[u8] = &self.data;
+        let mut files = &mut self.root;
+        for part in parent {
+            let entry = files
+                .iter_mut()
+                .find(|entry| entry.get_name() == part)
+                .ok_or_else(|| U8EditError::MissingParent(path.to_string()))?;
+            (data, files) = enter_dir(data, entry, path)?;
+        }
+        Ok((files, name))
+    }
+
+    /// marks this archive and the nested archives in the path as modified, after the
+    /// entry at the path was changed
+    fn mark_modified(&mut self, path: &str) {
+        self.modified = true;
+        // the archives in the path were already opened for the change, so this can't fail
+        let _ = find_entry_mut(&self.data, &mut self.root, &split_path(path), true);
+    }
+
+    /// `add_file`, but unsorted entries are added at the end of the directory
+    pub(crate) fn add_file_in_order(
+        &mut self,
+        path: &str,
+        data: Vec<u8>,
+        sorted: bool,
+    ) -> Result<(), U8EditError> {
+        let (files, name) = self.get_parent_mut(path)?;
+        check_name(name)?;
+        if files.iter().any(|entry| entry.get_name() == name) {
+            return Err(U8EditError::AlreadyExists(path.to_string()));
+        }
+        insert_entry(
+            files,
             Entry::FileEntry {
+                name: name.to_string(),
                 data: FileEntry::Data(data),
-                ..
-            } => Some(data),
-            &Entry::FileEntry {
-                data: FileEntry::Ref { offset, length },
-                ..
-            } => Some(&self.data[offset as usize..][..length as usize]),
+            },
+            sorted,
+        );
+        self.mark_modified(path);
+        Ok(())
+    }
+
+    /// creates the directory and all missing parent directories
+    pub fn create_dir_all(&mut self, path: &str) -> Result<(), U8EditError> {
+        self.create_dir_all_in_order(path, true)
+    }
+
+    /// `create_dir_all`, but unsorted entries are added at the end of the directory
+    pub(crate) fn create_dir_all_in_order(
+        &mut self,
+        path: &str,
+        sorted: bool,
+    ) -> Result<(), U8EditError> {
+        let parts = split_path(path);
+        // entering a directory can only fail before the first one is created
+        for part in &parts {
+            check_name(part)?;
         }
+        let mut created = false;
+        let mut data: &[u8] = &self.data;
+        let mut files = &mut self.root;
+        for part in parts {
+            let index = match files.iter().position(|entry| entry.get_name() == part) {
+                Some(index) => index,
+                None => {
+                    created = true;
+                    insert_entry(
+                        files,
+                        Entry::DirEntry {
+                            name: part.to_string(),
+                            files: Vec::new(),
+                        },
+                        sorted,
+                    )
+                }
+            };
+            (data, files) = enter_dir(data, &mut files[index], path)?;
+        }
+        if created {
+            self.mark_modified(path);
+        }
+        Ok(())
     }
 
-    /// returns a reference to the entry specified by the path
-    /// a starting "/" is ignored
-    pub fn get_entry<'b>(&'b self, path: &str) -> Option<&'b Entry> {
-        let mut parts_iter = path.split('/').peekable();
-        // allow starting with leading slash or not
-        if parts_iter.peek() == Some(&"") {
-            parts_iter.next();
-        }
-        // get the first entry
-        let first_part = parts_iter.next()?;
-        let mut entry = self
-            .root
+    /// removes a file or a directory with all its contents and returns it
+    pub fn remove(&mut self, path: &str) -> Result<Entry, U8EditError> {
+        let (files, name) = self.get_parent_mut(path)?;
+        let index = files
             .iter()
-            .find(|entry| entry.get_name() == first_part)?;
-        for part in parts_iter {
-            entry = match entry {
-                Entry::DirEntry { files, .. } => {
-                    files.iter().find(|entry| entry.get_name() == part)?
-                }
-                _ => return None,
-            }
+            .position(|entry| entry.get_name() == name)
+            .ok_or_else(|| U8EditError::NotFound(path.to_string()))?;
+        let entry = files.remove(index);
+        self.mark_modified(path);
+        Ok(entry)
+    }
+
+    /// renames a file or directory, it stays in the same directory
+    pub fn rename(&mut self, path: &str, new_name: &str) -> Result<(), U8EditError> {
+        check_name(new_name)?;
+        let (files, name) = self.get_parent_mut(path)?;
+        if name != new_name && files.iter().any(|entry| entry.get_name() == new_name) {
+            return Err(U8EditError::AlreadyExists(new_name.to_string()));
         }
-        Some(entry.borrow())
+        let entry = files
+            .iter_mut()
+            .find(|entry| entry.get_name() == name)
+            .ok_or_else(|| U8EditError::NotFound(path.to_string()))?;
+        *entry.name_mut() = new_name.to_string();
+        self.mark_modified(path);
+        Ok(())
+    }
+
+    /// moves a file or directory to a new path, the new parent directory has to exist
+    /// already
+    pub fn move_entry(&mut self, from: &str, to: &str) -> Result<(), U8EditError> {
+        let from_parts = split_path(from);
+        let to_parts = split_path(to);
+        if to_parts.starts_with(&from_parts) {
+            return Err(U8EditError::MoveIntoItself(from.to_string()));
+        }
+        // check the destination first, so that nothing is lost on errors
+        let (files, name) = self.get_parent_mut(to)?;
+        check_name(name)?;
+        if files.iter().any(|entry| entry.get_name() == name) {
+            return Err(U8EditError::AlreadyExists(to.to_string()));
+        }
+        let mut entry = self.remove(from)?;
+        *entry.name_mut() = name.to_string();
+        // can't fail anymore, the source wasn't an ancestor of the destination
+        let (files, _) = self.get_parent_mut(to)?;
+        insert_entry(files, entry, true);
+        self.mark_modified(to);
+        Ok(())
+    }
+
+    pub fn get_data_from_offset_len(&self, offset: u32, length: u32) -> &[u8] {
+        &self.data[offset as usize..][..length as usize]
+    }
+
+    /// the entry has to be directly in this archive, not in a nested one
+    pub fn get_data_from_entry<'b>(&'b self, entry: &'b Entry) -> Option<&'b [u8]> {
+        get_data_in(&self.data, entry)
+    }
+
+    /// returns a reference to the entry specified by the path
+    /// a starting "/" is ignored, paths can go through nested archives that are already
+    /// opened
+    pub fn get_entry<'b>(&'b self, path: &str) -> Option<&'b Entry> {
+        find_entry_in(&self.data, &self.root, &split_path(path)).map(|(_, entry)| entry)
     }
 
     /// returns a reference to the entry specified by the path
-    /// a starting "/" is ignored
+    /// a starting "/" is ignored, nested archives in the path are opened and count as
+    /// modified
     pub fn get_entry_mut<'b>(&'b mut self, path: &str) -> Option<&'b mut Entry> {
-        let mut parts_iter = path.split('/').peekable();
-        // allow starting with leading slash or not
-        if parts_iter.peek() == Some(&"") {
-            parts_iter.next();
-        }
-        // get the first entry
-        let first_part = parts_iter.next()?;
-        let mut entry = self
-            .root
-            .iter_mut()
-            .find(|entry| entry.get_name() == first_part)?;
-        for part in parts_iter {
-            entry = match entry {
-                Entry::DirEntry { files, .. } => {
-                    files.iter_mut().find(|entry| entry.get_name() == part)?
-                }
-                _ => return None,
-            }
-        }
-        Some(entry.borrow_mut())
+        let parts = split_path(path);
+        // only archives in a path that exists count as modified
+        find_entry_mut(&self.data, &mut self.root, &parts, false)
+            .ok()
+            .flatten()?;
+        self.modified = true;
+        find_entry_mut(&self.data, &mut self.root, &parts, true)
+            .ok()
+            .flatten()
+            .map(|(_, entry)| entry)
     }
 
     /// returns all full paths as a Vector
@@ -297,7 +623,27 @@ impl<'a> U8File<'a> {
         }
     }
 
+    /// writes the archive with the layout of the archive it was read from
     pub fn write<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        self.write_with_options(w, &U8WriteOptions::default())
+    }
+
+    pub fn write_with_options<W: Write>(
+        &self,
+        w: &mut W,
+        options: &U8WriteOptions,
+    ) -> io::Result<()> {
+        let layout = match options.alignment {
+            Some(_) => None,
+            None => self.layout.as_ref(),
+        };
+        let alignment = options
+            .alignment
+            .or(layout.map(|layout| layout.alignment))
+            .unwrap_or(DEFAULT_ALIGNMENT)
+            .max(1);
+        let first_node_offset = layout.map_or(0x20, |layout| layout.first_node_offset);
+
         let mut rebuild_entries = Vec::new();
         let mut string_pool = Vec::new();
         // root node
@@ -309,13 +655,7 @@ impl<'a> U8File<'a> {
         string_pool.push(0);
 
         // build structure for other nodes
-        self.do_rebuild_rec(
-            &self.root,
-            0,
-            &mut 0,
-            &mut rebuild_entries,
-            &mut string_pool,
-        );
+        self.do_rebuild_rec(&self.root, 0, &mut rebuild_entries, &mut string_pool)?;
         let next_parent_pos = rebuild_entries.len() as u32;
         match rebuild_entries.get_mut(0).unwrap() {
             RebuildEntry::Dir { next_parent, .. } => {
@@ -324,21 +664,54 @@ impl<'a> U8File<'a> {
             _ => unreachable!(),
         }
 
-        // actually write the data
-        w.write_u32::<BE>(MAGIC_HEADER)?;
-        // first node offset
-        w.write_u32::<BE>(0x20)?;
         // size of nodes and string pool
         let node_and_string_pool_size =
             rebuild_entries.len() as u32 * RawNode::SIZE + string_pool.len() as u32;
+        let unpadded_data_offset = first_node_offset + node_and_string_pool_size;
+        let data_offset = match layout {
+            Some(layout) if unpadded_data_offset <= layout.data_offset => layout.data_offset,
+            _ => align_next(unpadded_data_offset, alignment),
+        };
+
+        // place the file data, the original padding is kept as long as it still
+        // aligns the data
+        let mut current_pos = data_offset;
+        for node in rebuild_entries.iter_mut() {
+            let RebuildEntry::File {
+                data,
+                original_offset,
+                padding,
+                new_offset,
+                ..
+            } = node
+            else {
+                continue;
+            };
+            let original_padding = original_offset.and_then(|offset| {
+                let start = *layout?.padding_starts.get(&offset)?;
+                let original_padding = &self.data[start as usize..offset as usize];
+                (current_pos + original_padding.len() as u32)
+                    .is_multiple_of(alignment)
+                    .then_some(original_padding)
+            });
+            *padding = match original_padding {
+                Some(original_padding) => Padding::Original(original_padding),
+                None => Padding::Zero(align_next(current_pos, alignment) - current_pos),
+            };
+            *new_offset = current_pos + padding.len();
+            current_pos = *new_offset + data.len() as u32;
+        }
+
+        // actually write the data
+        w.write_u32::<BE>(MAGIC_HEADER)?;
+        w.write_u32::<BE>(first_node_offset)?;
         w.write_u32::<BE>(node_and_string_pool_size)?;
-        let unpadded_data_offset = 0x20 + node_and_string_pool_size;
-        let data_offset =
-            unpadded_data_offset + (unpadded_data_offset as isize).neg().rem_euclid(0x20) as u32;
         w.write_u32::<BE>(data_offset)?;
         // pad until node section
-        w.write_all(&[0; 16])?;
-        // let string_pool_offset = 0x20 + rebuild_entries.len() as u32 * RawNode::SIZE;
+        match layout {
+            Some(layout) => w.write_all(&self.data[0x10..layout.first_node_offset as usize])?,
+            None => w.write_all(&[0; 16])?,
+        }
 
         // nodes
         for node in rebuild_entries.iter() {
@@ -353,26 +726,15 @@ impl<'a> U8File<'a> {
                     w.write_u32::<BE>(parent)?;
                     w.write_u32::<BE>(next_parent)?;
                 }
-                &RebuildEntry::FileRef {
+                RebuildEntry::File {
                     str_offset,
-                    length,
-                    new_offset,
-                    ..
-                } => {
-                    w.write_u8(0)?;
-                    w.write_u24::<BE>(str_offset)?;
-                    w.write_u32::<BE>(data_offset + new_offset)?;
-                    w.write_u32::<BE>(length)?;
-                }
-                RebuildEntry::FileData {
-                    str_offset,
-                    new_offset,
                     data,
+                    new_offset,
                     ..
                 } => {
                     w.write_u8(0)?;
                     w.write_u24::<BE>(*str_offset)?;
-                    w.write_u32::<BE>(data_offset + *new_offset)?;
+                    w.write_u32::<BE>(*new_offset)?;
                     w.write_u32::<BE>(data.len() as u32)?;
                 }
             }
@@ -380,47 +742,40 @@ impl<'a> U8File<'a> {
 
         // string pool
         w.write_all(&string_pool)?;
+        match layout {
+            Some(layout) if unpadded_data_offset == layout.string_pool_end => {
+                w.write_all(&self.data[layout.string_pool_end as usize..data_offset as usize])?
+            }
+            _ => write_zeros(w, data_offset - unpadded_data_offset)?,
+        }
 
         // actual data
-        let mut current_pos = unpadded_data_offset;
-        let padding = [0; 32];
         for node in rebuild_entries.iter() {
-            let needed_padding = (current_pos as isize).neg().rem_euclid(0x20) as u32;
-            w.write_all(&padding[..needed_padding as usize])?;
-            current_pos += needed_padding;
-            match node {
-                RebuildEntry::Dir { .. } => continue,
-                RebuildEntry::FileData {
-                    data, new_offset, ..
-                } => {
-                    assert_eq!(current_pos, *new_offset + data_offset);
-                    w.write_all(data)?;
-                    current_pos += data.len() as u32;
-                }
-                &RebuildEntry::FileRef {
-                    offset,
-                    length,
-                    new_offset,
-                    ..
-                } => {
-                    assert_eq!(current_pos, new_offset + data_offset);
-                    w.write_all(&self.data[offset as usize..][..length as usize])?;
-                    current_pos += length;
+            if let RebuildEntry::File { data, padding, .. } = node {
+                match padding {
+                    Padding::Original(original_padding) => w.write_all(original_padding)?,
+                    Padding::Zero(len) => write_zeros(w, *len)?,
                 }
+                w.write_all(data)?;
+            }
+        }
+        if let Some(layout) = layout {
+            // whatever came after the last file
+            if current_pos == layout.data_end {
+                w.write_all(&self.data[layout.data_end as usize..])?;
             }
         }
 
         Ok(())
     }
 
-    fn do_rebuild_rec<'b: 'a>(
+    fn do_rebuild_rec<'b>(
         &'b self,
         files: &'b [Entry],
         parent: u32,
-        data_offset: &mut u32,
-        rebuild_entries: &mut Vec<RebuildEntry<'a>>,
+        rebuild_entries: &mut Vec<RebuildEntry<'b>>,
         string_pool: &mut Vec<u8>,
-    ) {
+    ) -> io::Result<()> {
         for entry in files.iter() {
             let str_offset = string_pool.len() as u32;
             string_pool.extend(entry.get_name().as_bytes());
@@ -438,10 +793,9 @@ impl<'a> U8File<'a> {
                     self.do_rebuild_rec(
                         sub_files,
                         current_entry_pos as u32,
-                        data_offset,
                         rebuild_entries,
                         string_pool,
-                    );
+                    )?;
                     let next_parent_pos = rebuild_entries.len() as u32;
                     match rebuild_entries.get_mut(current_entry_pos).unwrap() {
                         RebuildEntry::Dir { next_parent, .. } => {
@@ -450,57 +804,287 @@ impl<'a> U8File<'a> {
                         _ => unreachable!(),
                     }
                 }
-                &Entry::FileEntry {
-                    data: FileEntry::Ref { offset, length },
-                    ..
-                } => {
-                    rebuild_entries.push(RebuildEntry::FileRef {
-                        length,
-                        offset,
-                        str_offset,
-                        new_offset: *data_offset,
-                    });
-                    *data_offset += length;
-                    *data_offset += (*data_offset as isize).neg().rem_euclid(0x20) as u32;
-                }
-                Entry::FileEntry {
-                    data: FileEntry::Data(data),
-                    ..
-                } => {
-                    rebuild_entries.push(RebuildEntry::FileData {
+                Entry::FileEntry { data, .. } => {
+                    let original_offset = match data.unopened() {
+                        FileEntry::Ref { offset, .. } => Some(*offset),
+                        _ => None,
+                    };
+                    rebuild_entries.push(RebuildEntry::File {
                         str_offset,
-                        data,
-                        new_offset: *data_offset,
+                        data: self.get_file_bytes(entry)?,
+                        original_offset,
+                        padding: Padding::Zero(0), // filled in later
+                        new_offset: 0,             // filled in later
                     });
-                    *data_offset += data.len() as u32;
-                    *data_offset += (*data_offset as isize).neg().rem_euclid(0x20) as u32;
                 }
             }
         }
+        Ok(())
+    }
+
+    /// the data of a file entry, nested archives are written again
+    fn get_file_bytes<'b>(&'b self, entry: &'b Entry) -> io::Result<Cow<'b, [u8]>> {
+        match entry {
+            Entry::FileEntry {
+                data: FileEntry::Archive { archive, .. },
+                ..
+            } if archive.is_modified() => Ok(Cow::Owned(archive.write_compressed()?)),
+            _ => Ok(Cow::Borrowed(self.get_data_from_entry(entry).unwrap())),
+        }
+    }
+}
+
+/// options for `U8File::write_with_options`
+#[derive(Debug, Clone, Default)]
+pub struct U8WriteOptions {
+    /// aligns all file data to this instead of using the layout of the original archive,
+    /// the nodes then start at 0x20 and all padding is zero
+    pub alignment: Option<u32>,
+}
+
+/// how the archive that was read was laid out, so that it can be written the same way
+#[derive(Debug)]
+struct U8Layout {
+    first_node_offset: u32,
+    string_pool_end: u32,
+    data_offset: u32,
+    /// the largest alignment (up to the default) of all file data
+    alignment: u32,
+    /// for the data at each offset, where the padding before it starts
+    padding_starts: HashMap<u32, u32>,
+    /// end of the last file data
+    data_end: u32,
+}
+
+impl U8Layout {
+    /// None if the layout is too unusual to be reproduced
+    fn read(v: &[u8], root: &[Entry]) -> Option<Self> {
+        let mut ranges = Vec::new();
+        collect_data_ranges(root, &mut ranges);
+        Self::from_ranges(v, ranges)
+    }
+
+    /// like `read`, with the offset and length of every file data in the archive
+    fn from_ranges(v: &[u8], mut ranges: Vec<(u32, u32)>) -> Option<Self> {
+        let header_field = |offset: usize| Some(BE::read_u32(v.get(offset..offset + 4)?));
+        let first_node_offset = header_field(4)?;
+        let string_pool_end = first_node_offset.checked_add(header_field(8)?)?;
+        let data_offset = header_field(12)?;
+        if first_node_offset < 0x10 || string_pool_end > data_offset {
+            return None;
+        }
+
+        ranges.sort_unstable();
+        let mut alignment = DEFAULT_ALIGNMENT;
+        let mut padding_starts = HashMap::new();
+        let mut data_end = data_offset;
+        for (offset, length) in ranges {
+            if offset != 0 {
+                alignment = alignment.min(1 << offset.trailing_zeros());
+            }
+            padding_starts.insert(offset, data_end.min(offset));
+            data_end = data_end.max(offset.checked_add(length)?);
+        }
+        if data_end as usize > v.len() {
+            return None;
+        }
+        Some(U8Layout {
+            first_node_offset,
+            string_pool_end,
+            data_offset,
+            alignment,
+            padding_starts,
+            data_end,
+        })
+    }
+}
+
+fn collect_data_ranges(files: &[Entry], ranges: &mut Vec<(u32, u32)>) {
+    for entry in files {
+        match entry {
+            Entry::DirEntry { files, .. } => collect_data_ranges(files, ranges),
+            &Entry::FileEntry {
+                data: FileEntry::Ref { offset, length },
+                ..
+            } => ranges.push((offset, length)),
+            Entry::FileEntry { .. } => {}
+        }
+    }
+}
+
+#[inline]
+fn align_next(num: u32, alignment: u32) -> u32 {
+    num + (num as isize).neg().rem_euclid(alignment as isize) as u32
+}
+
+fn write_zeros<W: Write>(w: &mut W, len: u32) -> std::io::Result<()> {
+    std::io::copy(&mut std::io::repeat(0).take(len.into()), w)?;
+    Ok(())
+}
+
+enum Padding<'a> {
+    Original(&'a [u8]),
+    Zero(u32),
+}
+
+impl Padding<'_> {
+    fn len(&self) -> u32 {
+        match self {
+            Self::Original(padding) => padding.len() as u32,
+            Self::Zero(len) => *len,
+        }
     }
 }
 
 enum RebuildEntry<'a> {
     // str_offset is without the base stringpool offset
-    // data offset is without the base data offset
     Dir {
         str_offset: u32,
         parent: u32,
         next_parent: u32,
     },
-    FileRef {
-        str_offset: u32,
-        offset: u32,
-        length: u32,
-        new_offset: u32,
-    },
-    FileData {
+    File {
         str_offset: u32,
-        data: &'a Vec<u8>,
+        data: Cow<'a, [u8]>,
+        /// offset in the original archive, if the data wasn't replaced
+        original_offset: Option<u32>,
+        padding: Padding<'a>,
         new_offset: u32,
     },
 }
 
+/// reads the header, node table and string pool, everything else is only referenced
+fn read_root<RS: Read + Seek>(c: &mut RS) -> Result<Vec<Entry>, U8ParseError> {
+    c.seek(SeekFrom::Start(0))?;
+    let header = c.read_u32::<BE>()?;
+    if MAGIC_HEADER != header {
+        return Err(U8ParseError::InvalidMagic);
+    }
+    let first_node_offset = c.read_u32::<BE>()?;
+
+    let first_node = read_raw_node(c, first_node_offset)?;
+
+    let total_node_count = match first_node {
+        RawNode::RawDirNode {
+            next_parent_index, ..
+        } => next_parent_index,
+        _ => return Err(U8ParseError::InvalidNode),
+    };
+
+    let string_pool_offset = first_node_offset + total_node_count * 12;
+
+    // let root_node_name = read_ascii(&mut c, string_pool_offset)?;
+
+    read_nodes_recursive(
+        c,
+        1,
+        total_node_count,
+        first_node_offset,
+        string_pool_offset,
+    )
+}
+
+/// returns a reference to the entry specified by the path
+/// a starting "/" is ignored
+fn find_entry<'b>(root: &'b [Entry], path: &str) -> Option<&'b Entry> {
+    find_entry_in(&[], root, &split_path(path)).map(|(_, entry)| entry)
+}
+
+/// finds the entry and the data of the archive it's in, nested archives are only
+/// entered if they are already opened
+fn find_entry_in<'b>(
+    data: &'b [u8],
+    files: &'b [Entry],
+    parts: &[&str],
+) -> Option<(&'b [u8], &'b Entry)> {
+    let (first, rest) = parts.split_first()?;
+    let entry = files.iter().find(|entry| entry.get_name() == first)?;
+    if rest.is_empty() {
+        return Some((data, entry));
+    }
+    match entry {
+        Entry::DirEntry { files, .. } => find_entry_in(data, files, rest),
+        Entry::FileEntry {
+            data: FileEntry::Archive { archive, .. },
+            ..
+        } => find_entry_in(&archive.data, &archive.root, rest),
+        Entry::FileEntry { .. } => None,
+    }
+}
+
+/// like `find_entry_in`, but opens the nested archives in the path, they're marked as
+/// modified if `modify` is set
+fn find_entry_mut<'b>(
+    data: &'b [u8],
+    files: &'b mut [Entry],
+    parts: &[&str],
+    modify: bool,
+) -> Result<Option<(&'b [u8], &'b mut Entry)>, U8OpenError> {
+    let Some((first, rest)) = parts.split_first() else {
+        return Ok(None);
+    };
+    let Some(entry) = files.iter_mut().find(|entry| entry.get_name() == first) else {
+        return Ok(None);
+    };
+    if rest.is_empty() {
+        return Ok(Some((data, entry)));
+    }
+    match entry {
+        Entry::DirEntry { files, .. } => find_entry_mut(data, files, rest, modify),
+        Entry::FileEntry { data: file, .. } => {
+            let U8File {
+                data,
+                root,
+                modified,
+                ..
+            } = open_file_entry(data, file)?;
+            *modified |= modify;
+            find_entry_mut(data, root, rest, modify)
+        }
+    }
+}
+
+/// parses the file as an archive, unless that already happened
+fn open_file_entry<'b>(
+    data: &[u8],
+    file: &'b mut FileEntry,
+) -> Result<&'b mut U8File<'static>, U8OpenError> {
+    let file_data = match file {
+        &mut FileEntry::Ref { offset, length } => &data[offset as usize..][..length as usize],
+        FileEntry::Data(file_data) => file_data,
+        FileEntry::Archive { archive, .. } => return Ok(archive),
+    };
+    let archive = Box::new(U8File::read_compressed(file_data)?.into_owned());
+    let original = Box::new(std::mem::replace(file, FileEntry::Data(Vec::new())));
+    *file = FileEntry::Archive { archive, original };
+    match file {
+        FileEntry::Archive { archive, .. } => Ok(archive),
+        _ => unreachable!(),
+    }
+}
+
+fn get_data_in<'b>(data: &'b [u8], entry: &'b Entry) -> Option<&'b [u8]> {
+    match entry {
+        Entry::DirEntry { .. } => None,
+        Entry::FileEntry { data: file, .. } => match file.unopened() {
+            FileEntry::Data(data) => Some(data),
+            &FileEntry::Ref { offset, length } => Some(&data[offset as usize..][..length as usize]),
+            FileEntry::Archive { .. } => None,
+        },
+    }
+}
+
+fn any_archive_modified(files: &[Entry]) -> bool {
+    files.iter().any(|entry| match entry {
+        Entry::DirEntry { files, .. } => any_archive_modified(files),
+        Entry::FileEntry {
+            data: FileEntry::Archive { archive, .. },
+            ..
+        } => archive.is_modified(),
+        Entry::FileEntry { .. } => false,
+    })
+}
+
 fn read_nodes_recursive<RS: Read + Seek>(
     data: &mut RS,
     start_idx: u32,
@@ -548,3 +1132,223 @@ fn read_nodes_recursive<RS: Read + Seek>(
     }
     Ok(files)
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Compression, Entry, FileEntry, U8EditError, U8File, U8WriteOptions};
+
+    #[test]
+    pub fn test_edit() {
+        let mut arc = U8File::new();
+        arc.create_dir_all("/dat/sub").unwrap();
+        arc.add_file("dat/room.bzs", vec![1, 2, 3]).unwrap();
+        arc.add_file("dat/sub/a.bin", vec![4]).unwrap();
+        assert_eq!(
+            arc.add_file("dat/room.bzs", vec![]),
+            Err(U8EditError::AlreadyExists("dat/room.bzs".into()))
+        );
+        assert_eq!(
+            arc.add_file("missing/room.bzs", vec![]),
+            Err(U8EditError::MissingParent("missing/room.bzs".into()))
+        );
+        assert_eq!(
+            arc.create_dir_all("dat/room.bzs/x"),
+            Err(U8EditError::NotADirectory("dat/room.bzs/x".into()))
+        );
+        assert_eq!(
+            arc.add_file("dat/", vec![]),
+            Err(U8EditError::InvalidName("".into()))
+        );
+
+        arc.rename("dat/sub/a.bin", "b.bin").unwrap();
+        assert_eq!(
+            arc.rename("dat/sub", "room.bzs"),
+            Err(U8EditError::AlreadyExists("room.bzs".into()))
+        );
+        assert_eq!(
+            arc.move_entry("dat", "dat/sub/dat"),
+            Err(U8EditError::MoveIntoItself("dat".into()))
+        );
+        arc.move_entry("dat/sub", "sub2").unwrap();
+        assert!(matches!(
+            arc.remove("dat/sub"),
+            Err(U8EditError::NotFound(_))
+        ));
+
+        let mut buf = Vec::new();
+        arc.write(&mut buf).unwrap();
+        let arc = U8File::read(&buf).unwrap();
+        assert_eq!(
+            arc.get_all_paths(),
+            vec!["/dat/room.bzs".to_string(), "/sub2/b.bin".to_string()]
+        );
+        assert_eq!(arc.get_entry_data("sub2/b.bin"), Some(&[4u8][..]));
+
+        let mut arc = arc;
+        assert!(arc.remove("/dat").unwrap().is_dir());
+        assert_eq!(arc.get_all_paths(), vec!["/sub2/b.bin".to_string()]);
+
+        // new entries are sorted by name, ignoring case
+        arc.add_file("c.bin", vec![]).unwrap();
+        arc.create_dir_all("A/x").unwrap();
+        arc.move_entry("sub2/b.bin", "B.bin").unwrap();
+        let names: Vec<_> = arc.get_root_entry().iter().map(Entry::get_name).collect();
+        assert_eq!(names, ["A", "B.bin", "c.bin", "sub2"]);
+    }
+
+    fn file_offset(arc: &U8File, path: &str) -> usize {
+        match arc.get_entry(path) {
+            Some(&Entry::FileEntry {
+                data: FileEntry::Ref { offset, .. },
+                ..
+            }) => offset as usize,
+            _ => panic!("{path} is not in the archive"),
+        }
+    }
+
+    #[test]
+    pub fn test_rewrite_layout() {
+        let mut arc = U8File::new();
+        arc.add_file("a.bin", vec![1; 3]).unwrap();
+        arc.add_file("b.bin", vec![2; 5]).unwrap();
+        let options = U8WriteOptions { alignment: Some(4) };
+        let mut original = Vec::new();
+        arc.write_with_options(&mut original, &options).unwrap();
+
+        // fill the header, the gap after the string pool and the padding between the
+        // files with garbage, and add some trailing bytes
+        let (a_offset, b_offset) = {
+            let arc = U8File::read(&original).unwrap();
+            (file_offset(&arc, "a.bin"), file_offset(&arc, "b.bin"))
+        };
+        assert_eq!(a_offset % 4, 0);
+        assert_eq!(b_offset, a_offset + 4);
+        original[0x10..0x20].fill(0xCC);
+        original[a_offset - 1] = 0xDD;
+        original[a_offset + 3] = 0xDD;
+        original.extend([0xEE; 3]);
+
+        let arc = U8File::read(&original).unwrap();
+        let mut rewritten = Vec::new();
+        arc.write(&mut rewritten).unwrap();
+        assert_eq!(rewritten, original);
+
+        // data that doesn't fit anymore still gets aligned like the original
+        let mut arc = arc;
+        arc.set_entry_data("a.bin", vec![3; 6]);
+        let mut modified = Vec::new();
+        arc.write(&mut modified).unwrap();
+        let arc = U8File::read(&modified).unwrap();
+        assert_eq!(&modified[0x10..0x20], &[0xCC; 16]);
+        assert_eq!(file_offset(&arc, "a.bin"), a_offset);
+        assert_eq!(file_offset(&arc, "b.bin"), a_offset + 8);
+        assert_eq!(arc.get_entry_data("a.bin"), Some(&[3; 6][..]));
+        assert_eq!(arc.get_entry_data("b.bin"), Some(&[2; 5][..]));
+
+        // overriding the alignment ignores the original layout
+        let mut realigned = Vec::new();
+        let options = U8WriteOptions {
+            alignment: Some(0x20),
+        };
+        arc.write_with_options(&mut realigned, &options).unwrap();
+        let arc = U8File::read(&realigned).unwrap();
+        assert_eq!(&realigned[4..8], &[0, 0, 0, 0x20]);
+        assert_eq!(&realigned[0x10..0x20], &[0; 16]);
+        assert_eq!(file_offset(&arc, "a.bin") % 0x20, 0);
+        assert_eq!(file_offset(&arc, "b.bin") % 0x20, 0);
+    }
+
+    #[test]
+    pub fn test_nested_paths() {
+        let mut room = U8File::new();
+        room.create_dir_all("dat").unwrap();
+        room.add_file("dat/room.bzs", vec![1, 2, 3]).unwrap();
+        let mut room_data = Vec::new();
+        room.write(&mut room_data).unwrap();
+        room.set_compression(Compression::Yaz0);
+        let mut stage = U8File::new();
+        stage.create_dir_all("rarc").unwrap();
+        stage.add_file("rarc/r00.arc", room_data.clone()).unwrap();
+        stage
+            .add_file("rarc/r01.arc", room.write_compressed().unwrap())
+            .unwrap();
+        let mut stage_data = Vec::new();
+        stage.write(&mut stage_data).unwrap();
+
+        let mut stage = U8File::read(&stage_data).unwrap();
+        // nested archives have to be opened first
+        assert_eq!(stage.get_entry_data("rarc/r00.arc/dat/room.bzs"), None);
+        assert_eq!(
+            stage.read_entry_data("/rarc/r00.arc/dat/room.bzs").unwrap(),
+            Some(&[1, 2, 3][..])
+        );
+        assert!(stage.get_entry("rarc/r00.arc").unwrap().is_archive());
+        assert_eq!(
+            stage.get_entry_data("rarc/r00.arc/dat/room.bzs"),
+            Some(&[1, 2, 3][..])
+        );
+        assert_eq!(stage.read_entry_data("rarc/r00.arc/missing").unwrap(), None);
+        assert!(stage
+            .read_entry_data("rarc/r00.arc/dat/room.bzs/x")
+            .is_err());
+        assert!(!stage.set_entry_data("rarc/r00.arc/dat/room.bzs/x", vec![]));
+
+        // archives that are only read are written with their original data
+        stage.read_entry_data("rarc/r01.arc/dat/room.bzs").unwrap();
+        assert!(!stage.is_modified());
+        // as are archives where edits failed or didn't change anything
+        assert!(stage.remove("rarc/r00.arc/missing").is_err());
+        assert!(stage.rename("rarc/r00.arc/dat/missing", "x").is_err());
+        assert!(stage.add_file("rarc/r00.arc/dat/room.bzs", vec![]).is_err());
+        assert!(stage.create_dir_all("rarc/r00.arc/dat/").is_err());
+        stage.create_dir_all("rarc/r00.arc/dat").unwrap();
+        assert!(!stage.set_entry_data("rarc/r00.arc/dat", vec![]));
+        assert!(!stage.is_modified());
+        assert_eq!(stage.get_entry_data("rarc/r00.arc"), Some(&room_data[..]));
+        let mut rewritten = Vec::new();
+        stage.write(&mut rewritten).unwrap();
+        assert_eq!(rewritten, stage_data);
+
+        // compressed archives are opened on writes as well
+        assert!(stage.set_entry_data("rarc/r01.arc/dat/room.bzs", vec![4; 5]));
+        assert!(stage.is_modified());
+        assert_eq!(stage.get_entry_data("rarc/r01.arc"), None);
+        // edits follow the path into nested archives
+        stage.create_dir_all("rarc/r01.arc/dat/sub").unwrap();
+        stage
+            .add_file("rarc/r01.arc/dat/sub/a.bin", vec![6])
+            .unwrap();
+        stage
+            .move_entry("rarc/r01.arc/dat/sub/a.bin", "rarc/r01.arc/dat/new.bin")
+            .unwrap();
+        stage.remove("rarc/r01.arc/dat/sub").unwrap();
+        assert_eq!(
+            stage.add_file("rarc/r01.arc/dat/room.bzs/x", vec![]),
+            Err(U8EditError::NotADirectory(
+                "rarc/r01.arc/dat/room.bzs/x".into()
+            ))
+        );
+        assert!(stage.open_nested("rarc").unwrap().is_none());
+
+        // closing writes the archive once, further writes use that data
+        let closed = stage
+            .close_nested("rarc/r01.arc")
+            .unwrap()
+            .unwrap()
+            .to_vec();
+        assert!(!stage.get_entry("rarc/r01.arc").unwrap().is_archive());
+        assert_eq!(stage.close_nested("rarc/missing.arc").unwrap(), None);
+
+        let mut rewritten = Vec::new();
+        stage.write(&mut rewritten).unwrap();
+        let stage = U8File::read(&rewritten).unwrap();
+        // opened but unchanged archives are written the same
+        assert_eq!(stage.get_entry_data("rarc/r00.arc"), Some(&room_data[..]));
+        assert_eq!(stage.get_entry_data("rarc/r01.arc"), Some(&closed[..]));
+        let room = stage.get_entry_archive("rarc/r01.arc").unwrap().unwrap();
+        assert_eq!(room.compression(), Compression::Yaz0);
+        assert_eq!(room.get_entry_data("dat/room.bzs"), Some(&[4; 5][..]));
+        assert_eq!(room.get_entry_data("dat/new.bin"), Some(&[6][..]));
+        assert_eq!(room.get_all_paths(), ["/dat/new.bin", "/dat/room.bzs"]);
+    }
+}
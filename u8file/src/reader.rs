@@ -0,0 +1,103 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use crate::{find_entry, read_root, Entry, FileEntry, U8File, U8ParseError};
+
+/// reads only the node table and string pool of an archive, file data is read when
+/// it's requested
+pub struct U8Reader<R> {
+    reader: R,
+    root: Vec<Entry>,
+}
+
+impl<R: Read + Seek> U8Reader<R> {
+    pub fn new(mut reader: R) -> Result<Self, U8ParseError> {
+        let root = read_root(&mut reader)?;
+        Ok(U8Reader { reader, root })
+    }
+
+    pub fn get_root_entry(&self) -> &Vec<Entry> {
+        &self.root
+    }
+
+    /// returns a reference to the entry specified by the path
+    /// a starting "/" is ignored
+    pub fn get_entry(&self, path: &str) -> Option<&Entry> {
+        find_entry(&self.root, path)
+    }
+
+    /// returns all full paths as a Vector
+    pub fn get_all_paths(&self) -> Vec<String> {
+        let mut result = Vec::new();
+        U8File::collect_paths_rec("", &self.root, &mut result);
+        result
+    }
+
+    /// reads the data of the file at the path, None if it doesn't exist or is a directory
+    pub fn read_entry_data(&mut self, path: &str) -> Result<Option<Vec<u8>>, U8ParseError> {
+        let Some((offset, length)) = self.get_file_range(path) else {
+            return Ok(None);
+        };
+        let mut data = vec![0; length as usize];
+        self.reader.seek(SeekFrom::Start(offset.into()))?;
+        self.reader.read_exact(&mut data)?;
+        Ok(Some(data))
+    }
+
+    /// copies the data of the file at the path to the writer without buffering all of it,
+    /// returns false if it doesn't exist or is a directory
+    pub fn copy_entry_data<W: Write>(&mut self, path: &str, w: &mut W) -> io::Result<bool> {
+        let Some((offset, length)) = self.get_file_range(path) else {
+            return Ok(false);
+        };
+        self.reader.seek(SeekFrom::Start(offset.into()))?;
+        let copied = io::copy(&mut (&mut self.reader).take(length.into()), w)?;
+        if copied != u64::from(length) {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(true)
+    }
+
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    fn get_file_range(&self, path: &str) -> Option<(u32, u32)> {
+        match self.get_entry(path)? {
+            &Entry::FileEntry {
+                data: FileEntry::Ref { offset, length },
+                ..
+            } => Some((offset, length)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::U8Reader;
+    use crate::U8File;
+
+    #[test]
+    pub fn test_reader() {
+        let mut arc = U8File::new();
+        arc.create_dir_all("dat").unwrap();
+        arc.add_file("dat/room.bzs", vec![1, 2, 3]).unwrap();
+        arc.add_file("a.bin", vec![4; 0x50]).unwrap();
+        let mut buf = Vec::new();
+        arc.write(&mut buf).unwrap();
+
+        let mut reader = U8Reader::new(Cursor::new(buf)).unwrap();
+        assert_eq!(reader.get_all_paths(), vec!["/a.bin", "/dat/room.bzs"]);
+        assert_eq!(
+            reader.read_entry_data("/dat/room.bzs").unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(reader.read_entry_data("dat").unwrap(), None);
+        let mut copied = Vec::new();
+        assert!(reader.copy_entry_data("a.bin", &mut copied).unwrap());
+        assert_eq!(copied, vec![4; 0x50]);
+        assert!(!reader.copy_entry_data("b.bin", &mut copied).unwrap());
+    }
+}